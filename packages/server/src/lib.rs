@@ -1,44 +1,254 @@
-use std::path::Path;
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-use anyhow::Context;
+use anyhow::{Context, Result};
+use axum::extract::OriginalUri;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+
+/// Host the server listens on when none is configured.
+pub const DEFAULT_HOST: &str = "127.0.0.1";
+/// Port the server listens on when none is configured.
+pub const DEFAULT_PORT: u16 = 8000;
+
+/// Options used to launch the web server.
+pub struct ServerOptions {
+    /// Host name or IP address to listen on.
+    pub host: String,
+    /// Port to listen on.
+    ///
+    /// If it is taken, a free port is used instead.
+    pub port: u16,
+    /// URL path the served directory is mounted at.
+    pub base_url: String,
+    /// Path, relative to `base_url`, opened in the browser once the server is
+    /// listening.
+    ///
+    /// The browser isn't opened if it is `None`.
+    pub open: Option<String>,
+}
+
+/// Normalizes `base_url` so that it starts and ends with `/`.
+pub fn normalize_base_url(base_url: &str) -> String {
+    let trimmed = base_url.trim_matches('/');
+    if trimmed.is_empty() {
+        String::from("/")
+    } else {
+        format!("/{}/", trimmed)
+    }
+}
+
+/// A tree of files generated in memory.
+pub trait VirtualTree: Send + Sync + 'static {
+    /// Generates the contents of the file at `path`.
+    ///
+    /// `path` is relative to the base URL, it is percent-decoded and it doesn't
+    /// have `..` components. Returns `None` if the file doesn't exist.
+    fn generate(&self, path: &str) -> Result<Option<Vec<u8>>>;
+}
+
+/// A directory on disk served as is.
+struct Directory(PathBuf);
+
+impl VirtualTree for Directory {
+    fn generate(&self, path: &str) -> Result<Option<Vec<u8>>> {
+        let path = self.0.join(path);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        std::fs::read(&path)
+            .map(Some)
+            .with_context(|| format!("failed to read file {:?}", &path))
+    }
+}
+
+/// Serves `dir` until the process is interrupted.
+///
+/// Missing files are answered with `404.html` in `dir`, if any.
+pub fn launch_server(dir: &Path, options: &ServerOptions) -> Result<()> {
+    serve(
+        Arc::new(Directory(dir.to_path_buf())),
+        &format!("{:?}", dir),
+        options,
+    )
+}
+
+/// Serves the files in `tree` until the process is interrupted.
+///
+/// Every request generates the requested file again so that changes to the
+/// sources of the tree are picked up by reloading the page. Missing files are
+/// answered with `404.html` in `tree`, if any.
+pub fn launch_virtual_server(tree: impl VirtualTree, options: &ServerOptions) -> Result<()> {
+    serve(Arc::new(tree), "files in memory", options)
+}
+
+/// Answers the request for `path` with the file generated by `tree`.
+///
+/// Paths to directories are served their `index.html`.
+fn respond(tree: &dyn VirtualTree, path: &str) -> Response {
+    let path = percent_encoding::percent_decode_str(path).decode_utf8_lossy();
+    let path = path.trim_start_matches('/');
+    if Path::new(path)
+        .components()
+        .any(|component| !matches!(component, std::path::Component::Normal(_)))
+    {
+        return not_found(tree);
+    }
+
+    let candidates = if path.is_empty() || path.ends_with('/') {
+        vec![format!("{}index.html", path)]
+    } else {
+        vec![path.to_string(), format!("{}/index.html", path)]
+    };
+
+    for candidate in candidates {
+        match tree.generate(&candidate) {
+            Ok(Some(contents)) => return file_response(StatusCode::OK, &candidate, contents),
+            Ok(None) => continue,
+            Err(e) => {
+                log::error!("failed to generate {:?}: {:?}", &candidate, e);
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("failed to generate {:?}: {:?}", &candidate, e),
+                )
+                    .into_response();
+            },
+        }
+    }
+
+    not_found(tree)
+}
+
+/// Answers a request for a missing file with `404.html` in `tree`.
+fn not_found(tree: &dyn VirtualTree) -> Response {
+    match tree.generate("404.html") {
+        Ok(Some(contents)) => file_response(StatusCode::NOT_FOUND, "404.html", contents),
+        Ok(None) => (StatusCode::NOT_FOUND, "Not found").into_response(),
+        Err(e) => {
+            log::error!("failed to generate \"404.html\": {:?}", e);
+            (StatusCode::NOT_FOUND, "Not found").into_response()
+        },
+    }
+}
+
+/// Creates a response with `contents` of the file at `path`.
+///
+/// The content type is guessed from `path`.
+fn file_response(status: StatusCode, path: &str, contents: Vec<u8>) -> Response {
+    let mime = mime_guess::from_path(path).first_or_octet_stream();
+    let mut headers = axum::http::HeaderMap::new();
+    headers.insert(
+        axum::http::header::CONTENT_TYPE,
+        axum::http::HeaderValue::from_str(mime.as_ref()).unwrap(),
+    );
+    (status, headers, contents).into_response()
+}
+
+/// Serves `tree` under the configured base URL until the process is
+/// interrupted.
+///
+/// `description` describes what is served in the logs.
+fn serve(tree: Arc<dyn VirtualTree>, description: &str, options: &ServerOptions) -> Result<()> {
+    let service = axum::routing::get(
+        move |OriginalUri(original): OriginalUri, uri: axum::http::Uri| {
+            let tree = Arc::clone(&tree);
+            async move {
+                let path = uri.path().to_string();
+                let response =
+                    match tokio::task::spawn_blocking(move || respond(&*tree, &path)).await {
+                        Ok(response) => response,
+                        Err(e) => (
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            format!("Unhandled internal error: {}", e),
+                        )
+                            .into_response(),
+                    };
+                log::info!("GET {} {}", original.path(), response.status());
+                response
+            }
+        },
+    );
 
-pub fn launch_server(dir: &Path, open_browser: bool) {
     tokio::runtime::Builder::new_multi_thread()
         .enable_time()
         .enable_io()
         .build()
-        .unwrap()
+        .context("failed to start async runtime")?
         .block_on(async {
-            let address = [127, 0, 0, 1];
-            let port = 8000;
-            if open_browser {
+            let listener = bind(&options.host, options.port)?;
+            let address = listener
+                .local_addr()
+                .context("failed to get the server's address")?;
+
+            let base_url = normalize_base_url(&options.base_url);
+            let url = server_url(address, &base_url);
+            log::info!("serving {} at {}", description, &url);
+
+            if let Some(page) = &options.open {
+                let url = format!("{}{}", url, page.trim_start_matches('/'));
                 tokio::spawn(async move {
-                    let url = format!(
-                        "http://{}.{}.{}.{}:{}/preview",
-                        address[0], address[1], address[2], address[3], port
-                    );
                     if let Err(e) = open::that(url).context("failed to open browser") {
                         log::warn!("{}", e);
                     }
                 });
             }
 
-            let app = axum::Router::new().nest(
-                "/",
-                axum::routing::get_service(tower_http::services::ServeDir::new(&dir)).handle_error(
-                    |e| {
-                        async move {
-                            (
-                                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-                                format!("Unhandled internal error: {}", e),
-                            )
-                        }
-                    },
-                ),
-            );
-            axum::Server::bind(&std::net::SocketAddr::from((address, port)))
+            // `nest` expects paths without a trailing slash except for the root
+            let mount_path = match base_url.trim_end_matches('/') {
+                "" => "/",
+                path => path,
+            };
+            let app = axum::Router::new().nest(mount_path, service);
+
+            axum::Server::from_tcp(listener)
+                .context("failed to listen for connections")?
                 .serve(app.into_make_service())
                 .await
-                .unwrap();
-        });
+                .context("server failed")
+        })
+}
+
+/// Binds a listener to `host` and `port`.
+///
+/// If `port` is taken, the listener is bound to a free port chosen by the OS.
+fn bind(host: &str, port: u16) -> Result<TcpListener> {
+    let addresses = (host, port)
+        .to_socket_addrs()
+        .with_context(|| format!("failed to resolve host `{}`", host))?
+        .collect::<Vec<_>>();
+
+    match TcpListener::bind(&addresses[..]) {
+        Ok(listener) => Ok(listener),
+        Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => {
+            let addresses = addresses
+                .into_iter()
+                .map(|mut address| {
+                    address.set_port(0);
+                    address
+                })
+                .collect::<Vec<_>>();
+            let listener = TcpListener::bind(&addresses[..])
+                .with_context(|| format!("failed to bind to `{}`", host))?;
+            log::warn!(
+                "port {} is taken, using port {} instead",
+                port,
+                listener.local_addr()?.port()
+            );
+            Ok(listener)
+        },
+        Err(e) => Err(e).with_context(|| format!("failed to bind to `{}:{}`", host, port)),
+    }
+}
+
+/// Returns the URL of `base_url` in a server listening on `address`.
+fn server_url(address: SocketAddr, base_url: &str) -> String {
+    // a server listening on all interfaces is reachable through the loopback
+    // interface
+    let ip = match address.ip() {
+        std::net::IpAddr::V4(ip) if ip.is_unspecified() => std::net::Ipv4Addr::LOCALHOST.into(),
+        std::net::IpAddr::V6(ip) if ip.is_unspecified() => std::net::Ipv6Addr::LOCALHOST.into(),
+        ip => ip,
+    };
+    format!("http://{}{}", SocketAddr::new(ip, address.port()), base_url)
 }
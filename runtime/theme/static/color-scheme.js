@@ -0,0 +1,65 @@
+// Applies the color scheme chosen by the reader, or the system's one if they
+// didn't choose any. It is loaded before the page is shown to avoid flashing
+// the wrong colors.
+(function () {
+    const STORAGE_KEY = "codasai-color-scheme";
+    const darkQuery = window.matchMedia("(prefers-color-scheme: dark)");
+
+    let chosen = null;
+    try {
+        chosen = localStorage.getItem(STORAGE_KEY);
+    } catch (e) {
+        // storage may be disabled, the choice is kept for this page only
+    }
+
+    function current() {
+        return chosen || (darkQuery.matches ? "dark" : "light");
+    }
+
+    function apply() {
+        let root = document.documentElement;
+        if (chosen) {
+            root.dataset.theme = chosen;
+        } else {
+            delete root.dataset.theme;
+        }
+
+        // syntax themes follow the chosen scheme instead of the system's
+        let links = document.querySelectorAll("link[data-color-scheme]");
+        for (let i = 0; i < links.length; i++) {
+            const link = links[i];
+            const scheme = link.dataset.colorScheme;
+            if (chosen) {
+                link.media = scheme == chosen ? "all" : "not all";
+            } else {
+                link.media = `screen and (prefers-color-scheme: ${scheme})`;
+            }
+        }
+
+        let toggle = document.getElementById("color-scheme-toggle");
+        if (toggle) {
+            let icon = toggle.querySelector(".icon use");
+            let name = current() == "dark" ? "light_mode" : "dark_mode";
+            icon.setAttribute("href", icon.getAttribute("href").replace(/#.*$/, "#" + name));
+        }
+    }
+
+    apply();
+    darkQuery.addEventListener("change", apply);
+
+    document.addEventListener("DOMContentLoaded", () => {
+        apply();
+
+        let toggle = document.getElementById("color-scheme-toggle");
+        if (!toggle) {
+            return;
+        }
+        toggle.addEventListener("click", () => {
+            chosen = current() == "dark" ? "light" : "dark";
+            try {
+                localStorage.setItem(STORAGE_KEY, chosen);
+            } catch (e) {}
+            apply();
+        });
+    });
+})();
@@ -0,0 +1,223 @@
+use std::io::Read;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+/// Extracts the tar archive at `archive` to `dest`.
+///
+/// Archives ending in `.tar.gz` or `.tgz` are decompressed first. Only regular
+/// files and directories are extracted, links and other special entries are
+/// skipped.
+pub fn extract(archive: &Path, dest: &Path) -> Result<()> {
+    let file = std::fs::File::open(archive)
+        .with_context(|| format!("failed to open archive {:?}", archive))?;
+    let name = archive.to_string_lossy();
+    let reader: Box<dyn Read> = if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        Box::new(flate2::read::GzDecoder::new(file))
+    } else {
+        Box::new(file)
+    };
+
+    extract_tar(reader, dest).with_context(|| format!("failed to extract archive {:?}", archive))
+}
+
+/// Returns whether `path` has the extension of an archive [`extract`] can read.
+pub fn is_archive(path: &Path) -> bool {
+    let name = path.to_string_lossy();
+    name.ends_with(".tar") || name.ends_with(".tar.gz") || name.ends_with(".tgz")
+}
+
+fn extract_tar(reader: impl Read, dest: &Path) -> Result<()> {
+    std::fs::create_dir_all(dest)
+        .with_context(|| format!("failed to create directory {:?}", dest))?;
+
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries()? {
+        let mut entry = entry.context("failed to read entry")?;
+        let path = entry.path()?.into_owned();
+        match entry.header().entry_type() {
+            tar::EntryType::Regular | tar::EntryType::Continuous | tar::EntryType::Directory => {
+                // entries outside of `dest`, with `..` in their path, aren't
+                // unpacked
+                let unpacked = entry
+                    .unpack_in(dest)
+                    .with_context(|| format!("failed to extract {:?}", &path))?;
+                anyhow::ensure!(unpacked, "invalid path in archive {:?}", &path);
+            },
+            _ => log::debug!("skipping archive entry {:?}", &path),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns an archive with the entries in `headers`, along with their
+    /// contents.
+    fn archive(entries: Vec<(tar::Header, &[u8])>) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (mut header, data) in entries {
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append(&header, data).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    fn header(mut header: tar::Header, path: &str, entry_type: tar::EntryType) -> tar::Header {
+        header.set_path(path).unwrap();
+        header.set_entry_type(entry_type);
+        header
+    }
+
+    fn extracted(archive: &[u8]) -> Result<tempfile::TempDir> {
+        let dir = tempfile::tempdir().unwrap();
+        extract_tar(archive, dir.path())?;
+        Ok(dir)
+    }
+
+    #[test]
+    fn extracts_files_and_directories() {
+        let dir = extracted(&archive(vec![
+            (
+                header(
+                    tar::Header::new_ustar(),
+                    "theme/",
+                    tar::EntryType::Directory,
+                ),
+                b"",
+            ),
+            (
+                header(
+                    tar::Header::new_ustar(),
+                    "theme/theme.toml",
+                    tar::EntryType::Regular,
+                ),
+                b"name = \"dark\"",
+            ),
+            (
+                header(
+                    tar::Header::new_gnu(),
+                    "theme/templates/page.html",
+                    tar::EntryType::Regular,
+                ),
+                b"<main>",
+            ),
+        ]))
+        .unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("theme/theme.toml")).unwrap(),
+            "name = \"dark\""
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("theme/templates/page.html")).unwrap(),
+            "<main>"
+        );
+    }
+
+    #[test]
+    fn old_directories_end_with_a_slash() {
+        // V7 archives store directories as regular files ending with `/`
+        let dir = extracted(&archive(vec![
+            (
+                header(tar::Header::new_old(), "theme/", tar::EntryType::Regular),
+                b"",
+            ),
+            (
+                header(
+                    tar::Header::new_old(),
+                    "theme/a.css",
+                    tar::EntryType::Regular,
+                ),
+                b"a",
+            ),
+        ]))
+        .unwrap();
+
+        assert!(dir.path().join("theme").is_dir());
+        assert!(dir.path().join("theme/a.css").is_file());
+    }
+
+    #[test]
+    fn long_paths() {
+        let long_dir = "directory-with-a-long-name/".repeat(5);
+
+        // GNU archives store long paths in a `L` entry
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut gnu = tar::Header::new_gnu();
+        gnu.set_size(4);
+        builder
+            .append_data(&mut gnu, format!("{}gnu.css", long_dir), &b"gnu\n"[..])
+            .unwrap();
+
+        // PAX archives store them in an extended header, which can also
+        // override the entry's size
+        let records = format!("path={}pax.css\n", long_dir);
+        let records = format!("{} {}", records.len() + 4, records);
+        let records = format!("10 size=4\n{}", records);
+        let mut pax = tar::Header::new_ustar();
+        pax.set_path("PaxHeader").unwrap();
+        pax.set_entry_type(tar::EntryType::XHeader);
+        pax.set_size(records.len() as u64);
+        pax.set_cksum();
+        builder.append(&pax, records.as_bytes()).unwrap();
+        let mut file = header(tar::Header::new_ustar(), "pax.css", tar::EntryType::Regular);
+        file.set_size(0);
+        file.set_cksum();
+        builder.append(&file, &b"pax\n"[..]).unwrap();
+
+        let archive = builder.into_inner().unwrap();
+        let dir = extracted(&archive).unwrap();
+        let long_dir = dir.path().join(long_dir);
+        assert_eq!(
+            std::fs::read_to_string(long_dir.join("gnu.css")).unwrap(),
+            "gnu\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(long_dir.join("pax.css")).unwrap(),
+            "pax\n"
+        );
+    }
+
+    #[test]
+    fn links_are_skipped() {
+        let mut link = header(tar::Header::new_ustar(), "link", tar::EntryType::Symlink);
+        link.set_link_name("/etc/passwd").unwrap();
+        let dir = extracted(&archive(vec![
+            (link, b""),
+            (
+                header(tar::Header::new_ustar(), "file", tar::EntryType::Regular),
+                b"",
+            ),
+        ]))
+        .unwrap();
+
+        assert!(std::fs::symlink_metadata(dir.path().join("link")).is_err());
+        assert!(dir.path().join("file").is_file());
+    }
+
+    #[test]
+    fn paths_outside_of_destination_are_rejected() {
+        // `Header::set_path` refuses `..`, so it's written directly
+        let mut escaping = tar::Header::new_old();
+        escaping.as_old_mut().name[..10].copy_from_slice(b"../escaped");
+        escaping.set_entry_type(tar::EntryType::Regular);
+        let error = extracted(&archive(vec![(escaping, b"")])).unwrap_err();
+        assert!(error.to_string().contains("invalid path"));
+    }
+
+    #[test]
+    fn corrupted_headers_are_rejected() {
+        let mut archive = archive(vec![(
+            header(tar::Header::new_ustar(), "file", tar::EntryType::Regular),
+            b"contents",
+        )]);
+        archive[0] = b'g';
+        assert!(extracted(&archive).is_err());
+    }
+}
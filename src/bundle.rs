@@ -0,0 +1,1013 @@
+//! Bundles the theme's ES modules so that pages don't load every module the
+//! theme's scripts import.
+//!
+//! The modules are tokenized here instead of parsed by a bundler because only
+//! their top-level `import` and `export` statements are rewritten, and the
+//! bundlers that can be used from Rust, like swc, add a larger dependency tree
+//! than the rest of the crate. Modules the tokenizer can't handle are left as
+//! they are, which browsers load the same way.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::OsStr;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{Context, Result};
+use walkdir::WalkDir;
+
+/// Keywords after which a `/` starts a regular expression instead of being a
+/// division.
+const REGEX_KEYWORDS: [&str; 14] = [
+    "await",
+    "case",
+    "delete",
+    "do",
+    "else",
+    "in",
+    "instanceof",
+    "new",
+    "of",
+    "return",
+    "throw",
+    "typeof",
+    "void",
+    "yield",
+];
+
+/// Operators other than `=` that assign to the name before them.
+const ASSIGNMENT_OPERATORS: [&str; 17] = [
+    "+=", "-=", "*=", "/=", "%=", "**=", "<<=", ">>=", ">>>=", "&=", "|=", "^=", "&&=", "||=",
+    "??=", "++", "--",
+];
+
+/// An ES module rewritten to be evaluated inside a function of the bundle.
+struct Module {
+    /// Code of the module without its `import` and `export` statements.
+    body: String,
+    /// Paths of the modules it imports, relative to the bundled directory.
+    imports: Vec<PathBuf>,
+    /// Exported names along with the local names they refer to.
+    exports: Vec<(String, String)>,
+}
+
+/// Bundles every ES module in `dir` that imports other modules of `dir`,
+/// replacing it with a single file that contains the modules it imports.
+///
+/// Only the `import` and `export` statements at the top level of the modules
+/// are rewritten. Imports of modules outside of `dir`, like urls, are left as
+/// they are in the bundled module, but the modules it imports can't have any.
+/// Modules that can't be bundled, like modules that import each other, are
+/// left as they are with a warning.
+pub fn bundle_modules(dir: &Path) -> Result<()> {
+    let mut sources = BTreeMap::new();
+    for entry in WalkDir::new(dir).sort_by_file_name() {
+        let entry = entry.with_context(|| format!("failed to read directory {:?}", dir))?;
+        if entry.file_type().is_file() && entry.path().extension() == Some(OsStr::new("js")) {
+            let code = std::fs::read_to_string(entry.path())
+                .with_context(|| format!("failed to read file {:?}", entry.path()))?;
+            sources.insert(entry.path().strip_prefix(dir)?.to_path_buf(), code);
+        }
+    }
+
+    // only the modules that import others and the modules they import are
+    // parsed, so scripts that aren't modules are left alone
+    let mut modules = BTreeMap::new();
+    for (path, code) in &sources {
+        let bundle = match bundle_module(path, code, &sources, &mut modules) {
+            Ok(Some(bundle)) => bundle,
+            Ok(None) => continue,
+            Err(e) => {
+                log::warn!("{:?} isn't bundled: {:#}", dir.join(path), e);
+                continue;
+            },
+        };
+
+        let out_path = dir.join(path);
+        std::fs::write(&out_path, bundle)
+            .with_context(|| format!("failed to write to {:?}", &out_path))?;
+    }
+
+    Ok(())
+}
+
+/// Returns the module at `path` bundled with the modules it imports, or
+/// `None` if it doesn't import any module of `sources`.
+///
+/// Modules are parsed from `sources` into `modules` the first time they're
+/// imported.
+fn bundle_module(
+    path: &Path, code: &str, sources: &BTreeMap<PathBuf, String>,
+    modules: &mut BTreeMap<PathBuf, Module>,
+) -> Result<Option<String>> {
+    let (rewritten, imports) = rewrite_imports(path, code)?;
+    if imports.is_empty() {
+        return Ok(None);
+    }
+
+    let mut order = Vec::new();
+    let mut visiting = vec![path.to_path_buf()];
+    for import in &imports {
+        sort_imports(import, sources, modules, &mut visiting, &mut order)?;
+    }
+
+    let mut bundle = String::new();
+    // the module itself goes last, as it is, so that its exports still work
+    for import in &order {
+        let module = &modules[import];
+        bundle.push_str(&format!(
+            "const {} = (() => {{\n{}\nreturn {{ {} }};\n}})();\n",
+            module_variable(import),
+            module.body.trim_end(),
+            module
+                .exports
+                .iter()
+                .map(|(name, local)| format!("{}: {}", name, local))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+    }
+    bundle.push_str(&rewritten);
+
+    Ok(Some(bundle))
+}
+
+/// Adds the modules imported by the module at `path`, recursively, and then
+/// the module itself to `order` unless they're in it already.
+///
+/// Modules are parsed from `sources` into `modules` the first time they're
+/// found. `visiting` holds the modules whose imports are being added, to
+/// detect circular imports.
+fn sort_imports(
+    path: &Path, sources: &BTreeMap<PathBuf, String>, modules: &mut BTreeMap<PathBuf, Module>,
+    visiting: &mut Vec<PathBuf>, order: &mut Vec<PathBuf>,
+) -> Result<()> {
+    if order.iter().any(|sorted| sorted == path) {
+        return Ok(());
+    }
+    anyhow::ensure!(
+        !visiting.iter().any(|visited| visited == path),
+        "{:?} is imported circularly, which isn't supported",
+        path
+    );
+
+    if !modules.contains_key(path) {
+        let code = sources
+            .get(path)
+            .ok_or_else(|| anyhow::anyhow!("the imported module {:?} doesn't exist", path))?;
+        let module =
+            parse_module(path, code).with_context(|| format!("failed to parse {:?}", path))?;
+        modules.insert(path.to_path_buf(), module);
+    }
+
+    visiting.push(path.to_path_buf());
+    for import in modules[path].imports.clone() {
+        sort_imports(&import, sources, modules, visiting, order)?;
+    }
+    visiting.pop();
+
+    order.push(path.to_path_buf());
+    Ok(())
+}
+
+/// Parses the module at `path` with the contents `code`.
+fn parse_module(path: &Path, code: &str) -> Result<Module> {
+    let mut body = String::new();
+    let mut imports = Vec::new();
+    let mut exports = Vec::new();
+
+    let tokens = tokens(code)?;
+    let mut end = 0;
+    for (i, token) in module_statements(&tokens) {
+        body.push_str(&code[end..token.offset]);
+        let statement = &code[token.offset..];
+
+        if token.text == "import" {
+            let import = parse_import(path, statement)?;
+            let import_path = import.path.ok_or_else(|| {
+                anyhow::anyhow!(
+                    "`{}` isn't imported by a relative path, which is only supported in the \
+                     bundled module",
+                    &import.specifier
+                )
+            })?;
+            body.push_str(&import.declarations);
+            imports.push(import_path);
+            end = token.offset + import.len;
+            continue;
+        }
+
+        // what follows `export`
+        let declaration = &tokens[i + 1..];
+        match declaration.first().map(|token| token.text) {
+            Some("default") => {
+                let expression = &declaration[1..];
+                match declared_names(expression).as_deref() {
+                    Some([name]) => exports.push(("default".to_string(), name.clone())),
+                    _ => {
+                        body.push_str("const __default = ");
+                        exports.push(("default".to_string(), "__default".to_string()));
+                    },
+                }
+                end = expression.first().map_or(code.len(), |token| token.offset);
+            },
+            Some("{") => {
+                let list_end = declaration
+                    .iter()
+                    .position(|token| token.text == "}")
+                    .ok_or_else(|| anyhow::anyhow!("unterminated export list"))?;
+                let after_list = declaration.get(list_end + 1);
+                anyhow::ensure!(
+                    after_list.map(|token| token.text) != Some("from"),
+                    "re-exporting modules isn't supported"
+                );
+                let names = &code[declaration[0].offset + 1..declaration[list_end].offset];
+                for name in names.split(',').map(str::trim) {
+                    if name.is_empty() {
+                        continue;
+                    }
+                    match name.split_once(" as ") {
+                        Some((local, exported)) => {
+                            exports.push((exported.trim().to_string(), local.trim().to_string()))
+                        },
+                        None => exports.push((name.to_string(), name.to_string())),
+                    }
+                }
+                end = declaration[list_end].offset + 1;
+                if after_list.map(|token| token.text) == Some(";") {
+                    end += 1;
+                }
+            },
+            _ => {
+                let names = declared_names(declaration).ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "unsupported export `{}`",
+                        statement.lines().next().unwrap_or_default()
+                    )
+                })?;
+                exports.extend(names.into_iter().map(|name| (name.clone(), name)));
+                end = declaration[0].offset;
+            },
+        }
+    }
+    body.push_str(&code[end..]);
+
+    // the bundle exports the values of the bindings when the module is
+    // evaluated, so it can't update them for the modules that import them
+    let assigned = assigned_names(&tokens);
+    if let Some((_, local)) = exports.iter().find(|(_, local)| assigned.contains(local)) {
+        anyhow::bail!(
+            "{:?} assigns to its exported binding `{}` after declaring it, which isn't supported",
+            path,
+            local
+        );
+    }
+
+    Ok(Module {
+        body,
+        imports,
+        exports,
+    })
+}
+
+/// Replaces the `import` statements of the module at `path` that import other
+/// modules by relative paths with the values exported by the modules bundled
+/// before it.
+///
+/// Returns the rewritten module and the paths of the modules it imports.
+fn rewrite_imports(path: &Path, code: &str) -> Result<(String, Vec<PathBuf>)> {
+    let mut rewritten = String::new();
+    let mut imports = Vec::new();
+
+    let tokens = tokens(code)?;
+    let mut end = 0;
+    for (_, token) in module_statements(&tokens) {
+        if token.text != "import" {
+            continue;
+        }
+        let import = parse_import(path, &code[token.offset..])?;
+        if let Some(import_path) = import.path {
+            rewritten.push_str(&code[end..token.offset]);
+            rewritten.push_str(&import.declarations);
+            imports.push(import_path);
+            end = token.offset + import.len;
+        }
+    }
+    rewritten.push_str(&code[end..]);
+
+    Ok((rewritten, imports))
+}
+
+struct Import {
+    /// Specifier of the imported module, like `./state.js`.
+    specifier: String,
+    /// Path of the imported module, relative to the bundled directory, if it's
+    /// imported by a relative path.
+    path: Option<PathBuf>,
+    /// Declarations of the imported bindings.
+    declarations: String,
+    /// Length of the `import` statement.
+    len: usize,
+}
+
+/// Parses the `import` statement at the beginning of `statement`, in the
+/// module at `path`.
+fn parse_import(path: &Path, statement: &str) -> Result<Import> {
+    let clause_start = "import".len();
+    let quote_start = statement
+        .find(['"', '\''])
+        .ok_or_else(|| anyhow::anyhow!("missing module in import statement"))?;
+    let quote = &statement[quote_start..quote_start + 1];
+    let quote_end = statement[quote_start + 1..]
+        .find(quote)
+        .map(|end| quote_start + 1 + end)
+        .ok_or_else(|| anyhow::anyhow!("unterminated module in import statement"))?;
+    let specifier = &statement[quote_start + 1..quote_end];
+
+    let after_specifier = statement[quote_end + 1..].trim_start_matches([' ', '\t']);
+    anyhow::ensure!(
+        !after_specifier.starts_with("with") && !after_specifier.starts_with("assert"),
+        "import attributes aren't supported"
+    );
+    let mut len = quote_end + 1;
+    if statement[len..].starts_with(';') {
+        len += 1;
+    }
+
+    let import_path = resolve_import(path, specifier)?;
+    let module = import_path
+        .as_deref()
+        .map(module_variable)
+        .unwrap_or_default();
+
+    let clause = statement[clause_start..quote_start].trim();
+    let clause = clause.strip_suffix("from").unwrap_or(clause).trim();
+    let mut declarations = Vec::new();
+    let (default, rest) = match clause.find(['{', '*']) {
+        Some(start) => (
+            clause[..start].trim().trim_end_matches(','),
+            &clause[start..],
+        ),
+        None => (clause, ""),
+    };
+    let default = default.trim();
+    if !default.is_empty() {
+        declarations.push(format!("const {} = {}.default;", default, module));
+    }
+    if let Some(namespace) = rest.strip_prefix('*') {
+        let namespace = namespace.trim().trim_start_matches("as").trim();
+        declarations.push(format!("const {} = {};", namespace, module));
+    } else if let Some(names) = rest.strip_prefix('{') {
+        let names = names.trim_end().trim_end_matches('}');
+        let bindings = names
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(|name| match name.split_once(" as ") {
+                Some((imported, local)) => format!("{}: {}", imported.trim(), local.trim()),
+                None => name.to_string(),
+            })
+            .collect::<Vec<_>>();
+        declarations.push(format!("const {{ {} }} = {};", bindings.join(", "), module));
+    }
+
+    Ok(Import {
+        specifier: specifier.to_string(),
+        path: import_path,
+        declarations: declarations.join(" "),
+        len,
+    })
+}
+
+/// Returns the path, relative to the bundled directory, of the module
+/// imported as `specifier` by the module at `path`.
+///
+/// Returns `None` if `specifier` isn't a relative path, like a url or the
+/// name of a package.
+fn resolve_import(path: &Path, specifier: &str) -> Result<Option<PathBuf>> {
+    if !specifier.starts_with("./") && !specifier.starts_with("../") {
+        return Ok(None);
+    }
+
+    let mut resolved = path.parent().unwrap_or_else(|| Path::new("")).to_path_buf();
+    for component in Path::new(specifier).components() {
+        match component {
+            Component::CurDir => {},
+            Component::ParentDir => {
+                anyhow::ensure!(
+                    resolved.pop(),
+                    "`{}` is outside of the bundled directory",
+                    specifier
+                );
+            },
+            component => resolved.push(component),
+        }
+    }
+
+    Ok(Some(resolved))
+}
+
+/// Returns the `import` and `export` keywords in `tokens` that start the
+/// statements of a module, along with their index.
+///
+/// Dynamic `import()` and `import.meta` are expressions instead.
+fn module_statements<'a, 't>(
+    tokens: &'t [Token<'a>],
+) -> impl Iterator<Item = (usize, &'t Token<'a>)> {
+    tokens.iter().enumerate().filter(|(i, token)| {
+        let previous = i.checked_sub(1).map(|i| tokens[i].text);
+        let next = tokens.get(i + 1).map(|token| token.text);
+        token.depth == 0
+            && previous != Some(".")
+            && match token.text {
+                "import" => !matches!(next, Some("(" | ".")),
+                "export" => true,
+                _ => false,
+            }
+    })
+}
+
+/// Returns the names declared by the declaration that starts with `tokens`,
+/// like `function register()` or `let a = 1, b = 2`.
+///
+/// Returns `None` if it isn't a declaration or if it declares names by
+/// destructuring.
+fn declared_names(tokens: &[Token]) -> Option<Vec<String>> {
+    let name = |token: Option<&Token>| {
+        token
+            .map(|token| token.text)
+            .filter(|text| is_identifier(text) && *text != "extends")
+            .map(String::from)
+    };
+
+    let tokens = match tokens.first()?.text {
+        "async" if tokens.get(1)?.text == "function" => &tokens[1..],
+        _ => tokens,
+    };
+    match tokens.first()?.text {
+        "function" => {
+            let after_keyword = match tokens.get(1)?.text {
+                "*" => 2,
+                _ => 1,
+            };
+            Some(vec![name(tokens.get(after_keyword))?])
+        },
+        "class" => Some(vec![name(tokens.get(1))?]),
+        "const" | "let" | "var" => declarators(tokens)
+            .into_iter()
+            .map(|i| name(tokens.get(i)))
+            .collect(),
+        _ => None,
+    }
+}
+
+/// Returns the indices of the tokens after the `const`, `let` or `var` keyword
+/// that starts `tokens` where its declarators start.
+fn declarators(tokens: &[Token]) -> Vec<usize> {
+    let depth = tokens[0].depth;
+    let mut declarators = vec![1];
+    for (i, token) in tokens.iter().enumerate().skip(2) {
+        // a line break ends the declaration unless it's in the middle of a
+        // declarator
+        let continues = matches!(tokens[i - 1].text, "," | "=") || token.text == ",";
+        if token.depth < depth
+            || token.depth == depth && (token.text == ";" || token.after_line_break && !continues)
+        {
+            break;
+        }
+        if token.depth == depth && token.text == "," {
+            declarators.push(i + 1);
+        }
+    }
+    declarators
+}
+
+/// Returns the names in `tokens` that are assigned to, incremented or
+/// decremented anywhere but in their declarations.
+///
+/// Names are matched regardless of their scope and assignments by
+/// destructuring aren't found.
+fn assigned_names(tokens: &[Token]) -> BTreeSet<String> {
+    let text = |i: usize| tokens.get(i).map_or("", |token| token.text);
+    // whether the punctuation tokens from `i` are `operator`, without spaces
+    let is_operator = |i: usize, operator: &str| {
+        operator.bytes().enumerate().all(|(j, byte)| {
+            tokens.get(i + j).is_some_and(|token| {
+                token.text.as_bytes() == [byte] && token.offset == tokens[i].offset + j
+            })
+        })
+    };
+
+    let mut declarations = BTreeSet::new();
+    for (i, token) in tokens.iter().enumerate() {
+        if matches!(token.text, "const" | "let" | "var") && text(i.wrapping_sub(1)) != "." {
+            declarations.extend(declarators(&tokens[i..]).into_iter().map(|j| i + j));
+        }
+    }
+
+    let mut assigned = BTreeSet::new();
+    for (i, token) in tokens.iter().enumerate() {
+        if !is_identifier(token.text) || i > 0 && text(i - 1) == "." {
+            continue;
+        }
+        let after = i + 1;
+        let assignment = match text(after) {
+            "=" => !declarations.contains(&i) && !matches!(text(after + 1), "=" | ">"),
+            _ => ASSIGNMENT_OPERATORS
+                .iter()
+                .any(|operator| is_operator(after, operator)),
+        };
+        let prefixed = i >= 2 && (is_operator(i - 2, "++") || is_operator(i - 2, "--"));
+        if assignment || prefixed {
+            assigned.insert(token.text.to_string());
+        }
+    }
+    assigned
+}
+
+/// A token of javascript code, other than comments, strings, template
+/// literals and regular expressions, which are skipped.
+struct Token<'a> {
+    /// Offset of the token in the code.
+    offset: usize,
+    /// An identifier, a keyword, a number or a punctuation character.
+    text: &'a str,
+    /// Number of brackets around the token.
+    depth: usize,
+    /// Whether there is a line break between the token and the previous one.
+    after_line_break: bool,
+}
+
+/// Splits `code` into tokens.
+///
+/// This is only as precise as bundling needs: punctuation is split into
+/// single characters and whether a `/` starts a regular expression is guessed
+/// from the token before it.
+fn tokens(code: &str) -> Result<Vec<Token<'_>>> {
+    let bytes = code.as_bytes();
+    let mut tokens = Vec::new();
+    let mut depth = 0;
+    // depths of the template literal substitutions being scanned, like
+    // `${name}`
+    let mut substitutions = Vec::new();
+    let mut regex_allowed = true;
+    let mut after_line_break = false;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let start = i;
+        match bytes[i] {
+            b'\n' => {
+                after_line_break = true;
+                i += 1;
+                continue;
+            },
+            byte if byte.is_ascii_whitespace() => {
+                i += 1;
+                continue;
+            },
+            b'/' if bytes.get(i + 1) == Some(&b'/') => {
+                i = code[i..].find('\n').map_or(code.len(), |end| i + end);
+                continue;
+            },
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                let end = code[i + 2..]
+                    .find("*/")
+                    .map(|end| i + 2 + end + 2)
+                    .ok_or_else(|| anyhow::anyhow!("unterminated comment"))?;
+                after_line_break |= code[i..end].contains('\n');
+                i = end;
+                continue;
+            },
+            quote @ (b'"' | b'\'') => {
+                i = skip_string(code, i + 1, quote)?;
+                regex_allowed = false;
+            },
+            b'`' => {
+                let (end, substitution) = skip_template(code, i + 1)?;
+                if substitution {
+                    depth += 1;
+                    substitutions.push(depth);
+                }
+                i = end;
+                regex_allowed = substitution;
+            },
+            b'}' if substitutions.last() == Some(&depth) => {
+                substitutions.pop();
+                depth -= 1;
+                let (end, substitution) = skip_template(code, i + 1)?;
+                if substitution {
+                    depth += 1;
+                    substitutions.push(depth);
+                }
+                i = end;
+                regex_allowed = substitution;
+            },
+            b'/' if regex_allowed => {
+                i = skip_regex(code, i + 1)?;
+                regex_allowed = false;
+            },
+            byte if is_identifier_byte(byte) => {
+                while i < bytes.len() && is_identifier_byte(bytes[i]) {
+                    i += 1;
+                }
+                let text = &code[start..i];
+                tokens.push(Token {
+                    offset: start,
+                    text,
+                    depth,
+                    after_line_break,
+                });
+                regex_allowed = REGEX_KEYWORDS.contains(&text);
+            },
+            byte => {
+                if matches!(byte, b')' | b']' | b'}') {
+                    depth = depth.saturating_sub(1);
+                }
+                tokens.push(Token {
+                    offset: start,
+                    text: &code[start..start + 1],
+                    depth,
+                    after_line_break,
+                });
+                if matches!(byte, b'(' | b'[' | b'{') {
+                    depth += 1;
+                }
+                regex_allowed = !matches!(byte, b')' | b']');
+                i += 1;
+            },
+        }
+        after_line_break = false;
+    }
+
+    Ok(tokens)
+}
+
+/// Returns the offset after the string that starts at `start`, after its
+/// opening `quote`.
+fn skip_string(code: &str, start: usize, quote: u8) -> Result<usize> {
+    let bytes = code.as_bytes();
+    let mut i = start;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 1,
+            b'\n' => break,
+            byte if byte == quote => return Ok(i + 1),
+            _ => {},
+        }
+        i += 1;
+    }
+    anyhow::bail!("unterminated string at `{}`", line_at(code, start))
+}
+
+/// Returns the offset after the part of a template literal that starts at
+/// `start`, after its opening backtick or the end of a substitution.
+///
+/// The part ends with the template literal or with the start of a
+/// substitution, in which case `true` is returned along with the offset.
+fn skip_template(code: &str, start: usize) -> Result<(usize, bool)> {
+    let bytes = code.as_bytes();
+    let mut i = start;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 1,
+            b'`' => return Ok((i + 1, false)),
+            b'$' if bytes.get(i + 1) == Some(&b'{') => return Ok((i + 2, true)),
+            _ => {},
+        }
+        i += 1;
+    }
+    anyhow::bail!(
+        "unterminated template literal at `{}`",
+        line_at(code, start)
+    )
+}
+
+/// Returns the offset after the regular expression that starts at `start`,
+/// after its opening `/`.
+fn skip_regex(code: &str, start: usize) -> Result<usize> {
+    let bytes = code.as_bytes();
+    let mut in_class = false;
+    let mut i = start;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 1,
+            b'\n' => break,
+            b'[' => in_class = true,
+            b']' => in_class = false,
+            b'/' if !in_class => {
+                i += 1;
+                // flags
+                while i < bytes.len() && is_identifier_byte(bytes[i]) {
+                    i += 1;
+                }
+                return Ok(i);
+            },
+            _ => {},
+        }
+        i += 1;
+    }
+    anyhow::bail!(
+        "unterminated regular expression at `{}`",
+        line_at(code, start)
+    )
+}
+
+/// Returns whether `byte` can be part of an identifier or a number.
+///
+/// Bytes of non-ascii characters are assumed to be part of identifiers.
+fn is_identifier_byte(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || matches!(byte, b'_' | b'$') || !byte.is_ascii()
+}
+
+/// Returns whether `text` is an identifier, as opposed to a number or
+/// punctuation.
+fn is_identifier(text: &str) -> bool {
+    text.bytes()
+        .next()
+        .is_some_and(|byte| is_identifier_byte(byte) && !byte.is_ascii_digit())
+}
+
+/// Returns the rest of the line of `code` at `offset`, for error messages.
+fn line_at(code: &str, offset: usize) -> &str {
+    let line_start = code[..offset].rfind('\n').map_or(0, |start| start + 1);
+    code[line_start..].lines().next().unwrap_or_default().trim()
+}
+
+/// Returns the name of the variable that holds the exports of the module at
+/// `path` in a bundle.
+fn module_variable(path: &Path) -> String {
+    let mut name = String::from("__module_");
+    for c in path.to_string_lossy().chars() {
+        if c.is_ascii_alphanumeric() {
+            name.push(c);
+        } else {
+            name.push_str(&format!("_{:x}", c as u32));
+        }
+    }
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bundles the modules in `files` and returns the contents of the files
+    /// afterwards.
+    fn bundle(files: &[(&str, &str)]) -> BTreeMap<String, String> {
+        let dir = crate::testing::dir_with_files(files);
+        bundle_modules(dir.path()).unwrap();
+        files
+            .iter()
+            .map(|(path, _)| {
+                let code = std::fs::read_to_string(dir.path().join(path)).unwrap();
+                (path.to_string(), code)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn import_forms() {
+        let files = bundle(&[
+            (
+                "main.js",
+                "import a from \"./a.js\";\n\
+                 import { b, c as d } from './b.js';\n\
+                 import * as e from \"./e.js\"\n\
+                 import f, { g } from \"./f.js\";\n\
+                 import \"./side_effect.js\";\n\
+                 import {\n  h,\n  i,\n} from \"./dir/h.js\";\n\
+                 a(b, d, e, f, g, h, i);\n",
+            ),
+            ("a.js", "export default 1;\n"),
+            ("b.js", "export const b = 1;\nexport const c = 2;\n"),
+            ("e.js", "export const e = 1;\n"),
+            ("f.js", "export default 1;\nexport const g = 2;\n"),
+            ("side_effect.js", "console.log(1);\n"),
+            (
+                "dir/h.js",
+                "import { b } from \"../b.js\";\nexport let h = b, i = 2;\n",
+            ),
+        ]);
+
+        let main = &files["main.js"];
+        assert!(main.contains(
+            "const __module_a_2ejs = (() => {\nconst __default = 1;\nreturn { default: __default };"
+        ));
+        assert!(main.contains("const __module_side_5feffect_2ejs = (() => {\nconsole.log(1);"));
+        assert!(main.ends_with(
+            "const a = __module_a_2ejs.default;\n\
+             const { b, c: d } = __module_b_2ejs;\n\
+             const e = __module_e_2ejs;\n\
+             const f = __module_f_2ejs.default; const { g } = __module_f_2ejs;\n\n\
+             const { h, i } = __module_dir_2fh_2ejs;\n\
+             a(b, d, e, f, g, h, i);\n"
+        ));
+        // `b.js` is only included once, before the modules that import it
+        assert_eq!(main.matches("const __module_b_2ejs =").count(), 1);
+        assert!(main.find("const __module_b_2ejs =") < main.find("const __module_dir_2fh_2ejs ="));
+        assert!(main
+            .contains("const { b } = __module_b_2ejs;\nlet h = b, i = 2;\nreturn { h: h, i: i };"));
+        // modules that don't import others are left as they are
+        assert_eq!(files["a.js"], "export default 1;\n");
+    }
+
+    #[test]
+    fn export_forms() {
+        let module = parse_module(
+            Path::new("module.js"),
+            "export function a() {}\n\
+             export async function b() {}\n\
+             export function* c() {}\n\
+             export class D extends Object {}\n\
+             export const e = f(1, 2), g = [3, 4];\n\
+             export let h = 1,\n    i = { j: 2 }\n\
+             export var k\n\
+             l, m;\n\
+             export { l, m as n };\n\
+             export default function o() {}\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            module.body,
+            "function a() {}\n\
+             async function b() {}\n\
+             function* c() {}\n\
+             class D extends Object {}\n\
+             const e = f(1, 2), g = [3, 4];\n\
+             let h = 1,\n    i = { j: 2 }\n\
+             var k\n\
+             l, m;\n\n\
+             function o() {}\n"
+        );
+        let exports = module
+            .exports
+            .iter()
+            .map(|(name, local)| format!("{}: {}", name, local))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            exports,
+            [
+                "a: a",
+                "b: b",
+                "c: c",
+                "D: D",
+                "e: e",
+                "g: g",
+                "h: h",
+                "i: i",
+                "k: k",
+                "l: l",
+                "n: m",
+                "default: o",
+            ]
+        );
+    }
+
+    #[test]
+    fn default_exports_of_expressions() {
+        for (code, body) in [
+            ("export default { a: 1 };", "const __default = { a: 1 };"),
+            (
+                "export default function () {}",
+                "const __default = function () {}",
+            ),
+            (
+                "export default class extends Object {}",
+                "const __default = class extends Object {}",
+            ),
+            (
+                "export default async () => 1;",
+                "const __default = async () => 1;",
+            ),
+        ] {
+            let module = parse_module(Path::new("module.js"), code).unwrap();
+            assert_eq!(module.body, body);
+            assert_eq!(
+                module.exports,
+                [("default".to_string(), "__default".to_string())]
+            );
+        }
+    }
+
+    #[test]
+    fn non_relative_imports_are_left_untouched() {
+        let main = "import { html } from \"https://unpkg.com/lit?module\";\n\
+                    import lit from 'lit';\n\
+                    import a from \"./a.js\";\n\
+                    html(lit, a);\n";
+        let files = bundle(&[("main.js", main), ("a.js", "export default 1;\n")]);
+
+        assert!(files["main.js"].ends_with(
+            "import { html } from \"https://unpkg.com/lit?module\";\n\
+             import lit from 'lit';\n\
+             const a = __module_a_2ejs.default;\n\
+             html(lit, a);\n"
+        ));
+    }
+
+    #[test]
+    fn keywords_outside_of_statements_are_ignored() {
+        let main = "import a from \"./a.js\";\n\
+                    /*\n\
+                    import b from \"./missing.js\";\n\
+                    */\n\
+                    // import c from \"./missing.js\";\n\
+                    const template = `\n\
+                    import d from \"./missing.js\";\n\
+                    ${a.map((e) => `${e}\n\
+                    export const f = 1;\n\
+                    `)}`;\n\
+                    const g = \"import h from './missing.js'\";\n\
+                    const regex = /[\"'`]import/g;\n\
+                    const i = 1 / 2, j = '`';\n\
+                    function k() {\n\
+                    \x20 return import(\"./a.js\");\n\
+                    }\n\
+                    const l = import.meta.url + a.import;\n";
+        let files = bundle(&[("main.js", main), ("a.js", "export default [];\n")]);
+
+        assert!(files["main.js"].ends_with(&main.replace(
+            "import a from \"./a.js\";",
+            "const a = __module_a_2ejs.default;"
+        )));
+    }
+
+    #[test]
+    fn unsupported_modules_are_left_untouched() {
+        let main = "import a from \"./a.js\";\n";
+        for files in [
+            // circular imports
+            vec![
+                ("main.js", main),
+                ("a.js", "import b from \"./b.js\";\nexport default 1;\n"),
+                ("b.js", "import a from \"./a.js\";\nexport default 2;\n"),
+            ],
+            // non-relative imports in the imported modules
+            vec![
+                ("main.js", main),
+                ("a.js", "import lit from \"lit\";\nexport default lit;\n"),
+            ],
+            // re-exports
+            vec![("main.js", main), ("a.js", "export * from \"./b.js\";\n")],
+            vec![
+                ("main.js", main),
+                ("a.js", "export { b } from \"./b.js\";\n"),
+            ],
+            // destructuring
+            vec![("main.js", main), ("a.js", "export const { b, c } = {};\n")],
+            // import attributes
+            vec![
+                (
+                    "main.js",
+                    "import a from \"./a.json\" with { type: \"json\" };\n",
+                ),
+                ("a.json", "{}"),
+            ],
+            // missing modules and modules outside of the bundled directory
+            vec![("main.js", main)],
+            vec![("main.js", "import a from \"../a.js\";\n")],
+            // unterminated template literals
+            vec![("main.js", main), ("a.js", "export default `\n")],
+            // exported bindings that are assigned to, which the bundle wouldn't
+            // update
+            vec![
+                ("main.js", main),
+                (
+                    "a.js",
+                    "export let count = 0;\nexport function add() { count += 1; }\n",
+                ),
+            ],
+            vec![
+                ("main.js", main),
+                ("a.js", "let a = 1;\na = 2;\nexport { a as default };\n"),
+            ],
+        ] {
+            let bundled = bundle(&files);
+            for (path, code) in files {
+                assert_eq!(bundled[path], code);
+            }
+        }
+    }
+
+    #[test]
+    fn assignments_are_found() {
+        let assigned = |code| {
+            assigned_names(&tokens(code).unwrap())
+                .into_iter()
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            assigned("a = 1; b += 2; c++; --d; e ||= f; g >>>= 1; h **= 2;\n"),
+            ["a", "b", "c", "d", "e", "g", "h"]
+        );
+        assert_eq!(
+            assigned(
+                "let a = 1, b = 2;\n\
+                 const c = a == b || a != b || a <= b || a >= b, d = () => a;\n\
+                 obj.a = 3; obj.b++;\n\
+                 for (let i = 0, j = 1; i < j; ) {}\n"
+            ),
+            Vec::<String>::new()
+        );
+    }
+}
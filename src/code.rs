@@ -1,9 +1,20 @@
-use once_cell::unsync::Lazy;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use globset::{GlobBuilder, GlobMatcher};
+use sha2::{Digest, Sha256};
 use syntect::html::{ClassStyle, ClassedHTMLGenerator};
-use syntect::parsing::SyntaxSet;
+use syntect::parsing::{SyntaxDefinition, SyntaxReference, SyntaxSet};
 use syntect::util::LinesWithEndings;
+use walkdir::WalkDir;
 
+use crate::config::Config;
 use crate::html;
+use crate::theme::Theme;
 
 static SYNTAX_SET_DUMP_BIN: &[u8] = include_bytes!(concat!(
     env!("CARGO_MANIFEST_DIR"),
@@ -11,36 +22,349 @@ static SYNTAX_SET_DUMP_BIN: &[u8] = include_bytes!(concat!(
 ));
 
 thread_local! {
-    static SYNTAX_SET: Lazy<SyntaxSet> = Lazy::new(|| {
-        syntect::dumps::from_binary(SYNTAX_SET_DUMP_BIN)
-    });
+    /// The last syntax set used in this thread along with the hash of the
+    /// custom syntaxes it was built with.
+    static SYNTAX_SET: RefCell<Option<(String, Arc<SyntaxSet>)>> = const { RefCell::new(None) };
 }
 
 pub const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed {
     prefix: "csai-code-",
 };
 
-/// Escapes and highlights `code` using `ext` as the file extension to select
-/// the language's syntax.
+/// Highlights code with the bundled syntaxes and the custom syntaxes of a
+/// project.
+pub struct Highlighter {
+    syntax_set: Arc<SyntaxSet>,
+    /// Syntaxes configured for glob patterns.
+    globs: Vec<(GlobMatcher, String)>,
+    /// Syntaxes configured for file extensions.
+    extensions: BTreeMap<String, String>,
+    /// Syntaxes configured for file names.
+    filenames: BTreeMap<String, String>,
+}
+
+impl Highlighter {
+    /// Creates the highlighter of the project at `project`, whose theme is
+    /// `theme`.
+    ///
+    /// Custom syntaxes are the `.sublime-syntax` files in the theme's
+    /// `syntax/` directory and in `.codasai/syntaxes/`. They are merged with
+    /// the bundled syntaxes and the result is cached until they change.
+    pub fn from_project(project: &Path, theme: &Theme) -> Result<Self> {
+        let config = Config::from_project(project)?;
+        let syntax_set = syntax_set(project, theme).context("failed to load custom syntaxes")?;
+
+        let mappings = [
+            ("extensions", &config.highlight.extensions),
+            ("filenames", &config.highlight.filenames),
+        ];
+        for (table, mapping) in mappings {
+            for (key, syntax) in mapping {
+                anyhow::ensure!(
+                    syntax_set.find_syntax_by_token(syntax).is_some(),
+                    "`highlight.{}.{}` is set to `{}`, which is not a known syntax",
+                    table,
+                    key,
+                    syntax
+                );
+            }
+        }
+
+        let mut globs = Vec::new();
+        for glob in config.highlight.globs {
+            anyhow::ensure!(
+                syntax_set.find_syntax_by_token(&glob.syntax).is_some(),
+                "`highlight.globs` maps `{}` to `{}`, which is not a known syntax",
+                &glob.glob,
+                &glob.syntax
+            );
+            let matcher = GlobBuilder::new(&glob.glob)
+                .literal_separator(true)
+                .build()
+                .with_context(|| format!("invalid glob `{}` in `highlight.globs`", &glob.glob))?
+                .compile_matcher();
+            globs.push((matcher, glob.syntax));
+        }
+
+        Ok(Self {
+            syntax_set,
+            globs,
+            extensions: config.highlight.extensions,
+            filenames: config.highlight.filenames,
+        })
+    }
+
+    /// Escapes and highlights `code` using the syntax of the file at `path`,
+    /// relative to the workspace.
+    ///
+    /// The syntax is chosen by the configured globs, file names and
+    /// extensions, then by the file name, the file extension and the first
+    /// line of `code`, e.g. a shebang. If there is no syntax for the file, the
+    /// returned string is only escaped.
+    pub fn escape_and_highlight(&self, code: &str, path: &Path) -> String {
+        let syntax = match self.find_syntax(path, code) {
+            Some(syntax) => syntax,
+            None => return html::escape(code),
+        };
+
+        // SAFETY: syntect already escapes `code` so we don't escape it beforehand.
+        // If another library is used in the future, make sure `code` is
+        // escaped in this branch.
+        let mut html_generator =
+            ClassedHTMLGenerator::new_with_class_style(syntax, &self.syntax_set, CLASS_STYLE);
+
+        for line in LinesWithEndings::from(code) {
+            html_generator.parse_html_for_line_which_includes_newline(line);
+        }
+
+        html_generator.finalize()
+    }
+
+    fn find_syntax(&self, path: &Path, code: &str) -> Option<&SyntaxReference> {
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let extension = path.extension().unwrap_or_default().to_string_lossy();
+
+        let configured = self
+            .globs
+            .iter()
+            .find(|(glob, _)| glob.is_match(path))
+            .map(|(_, syntax)| syntax)
+            .or_else(|| self.filenames.get(file_name.as_ref()))
+            .or_else(|| self.extensions.get(extension.as_ref()));
+        if let Some(syntax) = configured {
+            return self.syntax_set.find_syntax_by_token(syntax);
+        }
+
+        // syntaxes list the file names they support, like `Makefile`, along
+        // with their extensions
+        self.syntax_set
+            .find_syntax_by_extension(&file_name)
+            .or_else(|| self.syntax_set.find_syntax_by_extension(&extension))
+            .or_else(|| {
+                let first_line = code.lines().next()?;
+                self.syntax_set.find_syntax_by_first_line(first_line)
+            })
+    }
+}
+
+/// Returns the bundled syntaxes merged with the custom syntaxes of the project
+/// at `project`.
 ///
-/// If the syntax highlighting doesn't support the file extension, the returned
-/// string is only escaped.
-pub fn escape_and_highlight(code: &str, ext: &str) -> String {
-    SYNTAX_SET.with(|ss| -> String {
-        if let Some(syntax) = ss.find_syntax_by_extension(ext) {
-            // SAFETY: syntect already escapes `code` so we don't escape it beforehand.
-            // If another library is used in the future, make sure `code` is
-            // escaped in this branch.
-            let mut html_generator =
-                ClassedHTMLGenerator::new_with_class_style(syntax, ss, CLASS_STYLE);
-
-            for line in LinesWithEndings::from(code) {
-                html_generator.parse_html_for_line_which_includes_newline(line);
+/// The merged syntaxes are dumped to the project's cache so that they are only
+/// built again when the custom syntaxes change.
+fn syntax_set(project: &Path, theme: &Theme) -> Result<Arc<SyntaxSet>> {
+    let sources = custom_syntaxes(project, theme)?;
+    let hash = sources_hash(&sources);
+
+    let cached = SYNTAX_SET.with(|cached| match &*cached.borrow() {
+        Some((cached_hash, syntax_set)) if *cached_hash == hash => Some(Arc::clone(syntax_set)),
+        _ => None,
+    });
+    if let Some(syntax_set) = cached {
+        return Ok(syntax_set);
+    }
+
+    let syntax_set = if sources.is_empty() {
+        syntect::dumps::from_binary(SYNTAX_SET_DUMP_BIN)
+    } else {
+        let dump_path =
+            crate::paths::cache_dir(project, "syntaxes").join(format!("{}.packdump", &hash));
+        match syntect::dumps::from_dump_file(&dump_path) {
+            Ok(syntax_set) => syntax_set,
+            Err(_) => {
+                let syntax_set = build_syntax_set(&sources)?;
+                cache_syntax_set(&syntax_set, &dump_path);
+                syntax_set
+            },
+        }
+    };
+
+    let syntax_set = Arc::new(syntax_set);
+    SYNTAX_SET.with(|cached| *cached.borrow_mut() = Some((hash, Arc::clone(&syntax_set))));
+    Ok(syntax_set)
+}
+
+/// Returns the SHA-256 digest of `sources` and the codasai version, which keys
+/// the cached syntaxes since it's the same on every platform and build.
+fn sources_hash(sources: &[(PathBuf, String)]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(env!("CARGO_PKG_VERSION"));
+    for (path, contents) in sources {
+        // the lengths keep the boundaries between the fields unambiguous
+        let path = path.to_string_lossy();
+        for field in [path.as_bytes(), contents.as_bytes()] {
+            hasher.update((field.len() as u64).to_le_bytes());
+            hasher.update(field);
+        }
+    }
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Returns the path and contents of the custom syntaxes of the project at
+/// `project`, whose theme is `theme`.
+fn custom_syntaxes(project: &Path, theme: &Theme) -> Result<Vec<(PathBuf, String)>> {
+    let is_syntax = |path: &Path| path.extension() == Some(OsStr::new("sublime-syntax"));
+    let mut sources = Vec::new();
+
+    for path in theme.files(Path::new("syntax"))? {
+        if is_syntax(&path) {
+            let contents = theme.file(&path)?.unwrap();
+            let contents = String::from_utf8(contents.into_owned())
+                .with_context(|| format!("syntax {:?} is not valid utf-8", &path))?;
+            sources.push((path, contents));
+        }
+    }
+
+    let syntaxes_dir = project.join(".codasai/syntaxes");
+    if syntaxes_dir.is_dir() {
+        for entry in WalkDir::new(&syntaxes_dir).sort_by_file_name() {
+            let entry =
+                entry.with_context(|| format!("failed to read directory {:?}", &syntaxes_dir))?;
+            if entry.file_type().is_file() && is_syntax(entry.path()) {
+                let contents = std::fs::read_to_string(entry.path())
+                    .with_context(|| format!("failed to read syntax {:?}", entry.path()))?;
+                sources.push((entry.path().to_path_buf(), contents));
             }
+        }
+    }
+
+    Ok(sources)
+}
+
+fn build_syntax_set(sources: &[(PathBuf, String)]) -> Result<SyntaxSet> {
+    let bundled: SyntaxSet = syntect::dumps::from_binary(SYNTAX_SET_DUMP_BIN);
+    let mut builder = bundled.into_builder();
+    for (path, contents) in sources {
+        let fallback_name = path.file_stem().map(|stem| stem.to_string_lossy());
+        let syntax = SyntaxDefinition::load_from_str(contents, true, fallback_name.as_deref())
+            .with_context(|| format!("failed to parse syntax {:?}", path))?;
+        builder.add(syntax);
+    }
+    Ok(builder.build())
+}
+
+/// Dumps `syntax_set` to `dump_path` and removes the other dumps next to it,
+/// which are of syntaxes that changed since.
+///
+/// Failing to do so isn't an error since the syntaxes can be built again.
+fn cache_syntax_set(syntax_set: &SyntaxSet, dump_path: &Path) {
+    let cache_dir = dump_path.parent().unwrap();
+    let result = std::fs::create_dir_all(cache_dir)
+        .map_err(anyhow::Error::from)
+        .and_then(|_| Ok(syntect::dumps::dump_to_file(syntax_set, dump_path)?));
+    if let Err(e) = result {
+        log::warn!("failed to cache syntaxes to {:?}: {}", dump_path, e);
+        return;
+    }
 
-            html_generator.finalize()
-        } else {
-            html::escape(code)
+    let result = std::fs::read_dir(cache_dir).and_then(|entries| {
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension() == Some(OsStr::new("packdump")) && path != dump_path {
+                std::fs::remove_file(&path)?;
+            }
         }
-    })
+        Ok(())
+    });
+    if let Err(e) = result {
+        log::warn!("failed to remove old syntaxes from {:?}: {}", cache_dir, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestRepo;
+
+    #[test]
+    fn first_matching_glob_wins() {
+        let test = TestRepo::project();
+        let config = test.path().join(".codasai/guide.toml");
+        let mut contents = std::fs::read_to_string(&config).unwrap();
+        contents.push_str(concat!(
+            "\n[[highlight.globs]]\nglob = \"scripts/python/*\"\nsyntax = \"python\"\n",
+            "\n[[highlight.globs]]\nglob = \"scripts/**\"\nsyntax = \"bash\"\n",
+        ));
+        std::fs::write(&config, contents).unwrap();
+
+        let theme = Theme::from_project(test.path()).unwrap();
+        let highlighter = Highlighter::from_project(test.path(), &theme).unwrap();
+        let syntax = |path: &str| {
+            highlighter
+                .find_syntax(Path::new(path), "")
+                .unwrap()
+                .name
+                .clone()
+        };
+        assert_eq!(syntax("scripts/python/setup"), "Python");
+        assert_eq!(syntax("scripts/ci/setup"), "Bourne Again Shell (bash)");
+        assert_eq!(syntax("src/main.rs"), "Rust");
+    }
+
+    #[test]
+    fn syntaxes_are_found_by_file_name_and_first_line() {
+        let test = TestRepo::project();
+        let theme = Theme::from_project(test.path()).unwrap();
+        let highlighter = Highlighter::from_project(test.path(), &theme).unwrap();
+        let syntax = |path: &str, code: &str| {
+            highlighter
+                .find_syntax(Path::new(path), code)
+                .map(|syntax| syntax.name.clone())
+        };
+        assert_eq!(
+            syntax("scripts/setup", "#!/usr/bin/env python\nprint(1)\n").as_deref(),
+            Some("Python")
+        );
+        assert_eq!(syntax("Makefile", "all:\n").as_deref(), Some("Makefile"));
+        assert_eq!(
+            syntax("docker/Dockerfile", "FROM rust\n").as_deref(),
+            Some("Dockerfile")
+        );
+        assert_eq!(syntax("scripts/setup", "setup\n"), None);
+    }
+
+    #[test]
+    fn custom_syntaxes_highlight_files_and_are_cached() {
+        let test = TestRepo::project();
+        let syntax = |keyword: &str| {
+            format!(
+                "%YAML 1.2\n---\nname: Zon\nfile_extensions: [zon]\nscope: source.zon\n\
+                 contexts:\n  main:\n    - match: '\\b{}\\b'\n      scope: keyword.zon\n",
+                keyword
+            )
+        };
+        let syntax_path = test.path().join(".codasai/syntaxes/zon.sublime-syntax");
+        let dumps = || {
+            std::fs::read_dir(crate::paths::cache_dir(test.path(), "syntaxes"))
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+        };
+        let theme = Theme::from_project(test.path()).unwrap();
+
+        std::fs::create_dir_all(syntax_path.parent().unwrap()).unwrap();
+        std::fs::write(&syntax_path, syntax("foo")).unwrap();
+        let highlighter = Highlighter::from_project(test.path(), &theme).unwrap();
+        assert_eq!(
+            highlighter.escape_and_highlight("foo bar", Path::new("build.zon")),
+            "<span class=\"csai-code-source csai-code-zon\">\
+             <span class=\"csai-code-keyword csai-code-zon\">foo</span> bar</span>"
+        );
+        let first_dumps = dumps();
+        assert_eq!(first_dumps.len(), 1);
+
+        // the dump of the previous syntaxes is replaced
+        std::fs::write(&syntax_path, syntax("bar")).unwrap();
+        let highlighter = Highlighter::from_project(test.path(), &theme).unwrap();
+        assert!(highlighter
+            .escape_and_highlight("foo bar", Path::new("build.zon"))
+            .contains("<span class=\"csai-code-keyword csai-code-zon\">bar</span>"));
+        let second_dumps = dumps();
+        assert_eq!(second_dumps.len(), 1);
+        assert_ne!(first_dumps, second_dumps);
+    }
 }
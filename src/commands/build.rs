@@ -1,27 +1,66 @@
 use std::ffi::OsString;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use anyhow::{Context, Result};
 use clap::Parser;
 
+use crate::code::Highlighter;
+use crate::config::Config;
 use crate::context::{
-    Directory, GlobalContext, GuideContext, Index, PageContext, WorkspaceOutlineBuilder,
+    Directory, GlobalContext, GuideContext, Index, PageContext, VariantEntry, VariantsContext,
+    WorkspaceOutlineBuilder,
 };
+use crate::export::BuildMode;
+use crate::external::ExternalResources;
 use crate::page::PagePreprocessor;
-use crate::paths;
+use crate::target::Target;
+use crate::theme::Theme;
+use crate::{history, paths};
 
 #[derive(Parser)]
 pub struct Opts {
     /// Indicates under what url the exported files will be. Useful for sites
     /// like Github/Gitlab pages
     ///
-    /// By default, it is the current domain's root.
-    #[clap(long, default_value = "/")]
-    base_url: String,
+    /// By default, it is the current domain's root or the one used by
+    /// `--target`.
+    #[clap(long)]
+    base_url: Option<String>,
 
     /// Directory where output files will be stored.
     #[clap(long)]
     export_dir: Option<PathBuf>,
+
+    /// Git reference (branch, tag or commit) the guide is built from.
+    ///
+    /// By default, the guide is built from `HEAD` or, if the guide has
+    /// variants, every variant is built from its own reference.
+    #[clap(long = "ref")]
+    reference: Option<String>,
+
+    /// Static site host the guide is built for.
+    ///
+    /// It sets the base URL used by the host and exports the files it needs.
+    #[clap(long, arg_enum)]
+    target: Option<Target>,
+
+    /// Checks the built guide for resources loaded from other hosts, like
+    /// fonts or scripts from a CDN, and warns about them or fails.
+    ///
+    /// Guides that load external resources don't work offline.
+    #[clap(long, arg_enum)]
+    external_resources: Option<ExternalResources>,
+
+    /// Minifies the html, compresses the css and bundles the javascript
+    /// modules of the exported guide.
+    #[clap(long)]
+    production: bool,
+
+    /// Writes gzip and brotli compressed copies of the exported text files,
+    /// e.g. `index.html.gz` and `index.html.br`, for hosts that serve them.
+    #[clap(long)]
+    precompress: bool,
 }
 
 pub fn execute(opts: &Opts) -> Result<()> {
@@ -29,120 +68,307 @@ pub fn execute(opts: &Opts) -> Result<()> {
     if let Some(export_dir) = opts.export_dir.clone() {
         project_paths.set_export(export_dir);
     }
-    let project = project_paths.project().clone();
 
-    crate::export::export_public_files(&project_paths)?;
-    let index = Index::from_project(&project)?;
-    let guide_ctx = GuideContext {
-        index: index.clone(),
-        base_url: opts.base_url.clone(),
+    let base_url = match (&opts.base_url, opts.target) {
+        (Some(base_url), _) => base_url.clone(),
+        (None, Some(target)) => {
+            let repo = git2::Repository::open(project_paths.project()).with_context(|| {
+                format!("failed to open repository at {:?}", project_paths.project())
+            })?;
+            target.base_url(&repo)?
+        },
+        (None, None) => String::from("/"),
+    };
+
+    let mode = if opts.production {
+        BuildMode::Production
+    } else {
+        BuildMode::Development
     };
-    let preprocessor = PagePreprocessor::new(&guide_ctx);
+    build(&project_paths, opts.reference.as_deref(), &base_url, mode)?;
+
+    if let Some(target) = opts.target {
+        let start_page = start_page(&project_paths, opts.reference.as_deref())?;
+        target.export_host_files(project_paths.export(), &base_url, start_page.as_deref())?;
+    }
+
+    if let Some(policy) = opts.external_resources {
+        crate::external::check(project_paths.export(), policy)?;
+    }
 
+    // last, so that the files exported for the target are compressed too
+    if opts.precompress {
+        crate::compress::precompress(project_paths.export())
+            .context("failed to compress the exported files")?;
+    }
+
+    Ok(())
+}
+
+/// Returns the code of the first page of the guide built from `reference`.
+///
+/// It is `None` if the guide has no pages or if it has variants and
+/// `reference` is `None` since the guide has a landing page instead.
+pub fn start_page(
+    project_paths: &paths::ProjectPaths, reference: Option<&str>,
+) -> Result<Option<String>> {
+    let project = project_paths.project();
+    let config = Config::from_project(project)?;
+    if reference.is_none() && !config.variants.is_empty() {
+        return Ok(None);
+    }
+
+    let repo = git2::Repository::open(project)
+        .with_context(|| format!("failed to open repository at {:?}", project))?;
+    let reference = reference.unwrap_or("HEAD");
+    let head = repo
+        .revparse_single(reference)
+        .and_then(|object| object.peel_to_commit())
+        .with_context(|| format!("failed to resolve reference `{}`", reference))?;
+
+    let index = guide_index(project, &repo, &head)?;
+    Ok(index.entries.into_iter().next().map(|entry| entry.code))
+}
+
+/// Builds the guide into the project's export directory.
+///
+/// The guide is built from `reference` or, if it is `None`, from `HEAD` or
+/// every configured variant.
+pub fn build(
+    project_paths: &paths::ProjectPaths, reference: Option<&str>, base_url: &str, mode: BuildMode,
+) -> Result<()> {
+    let project = project_paths.project().clone();
+    let config = Config::from_project(&project)?;
+
+    let theme = Arc::new(Theme::from_project(&project)?);
     let repo = git2::Repository::open(&project)
         .with_context(|| format!("failed to open repository at {:?}", &project))?;
 
-    let mut page_num = 0;
-    let mut last_rev = None;
-    for rev in revwalk(&repo)? {
-        let rev = rev.context("failed to retrieve rev")?;
-
-        let (file_name, page) = if let Some((name, page)) = find_new_page(&repo, last_rev, rev)? {
-            (name, page)
-        } else {
-            continue;
-        };
-
-        let tree = repo.find_commit(rev)?.tree()?;
-        let workspace_outline =
-            build_workspace_outline(&repo, &tree).context("failed to build workspace outline")?;
-        let page_ctx = PageContext {
-            number: page_num,
-            title: crate::page::extract_title(&page),
-            code: index.entries[page_num].code.clone(),
-            content: crate::page::markdown_to_html(&preprocessor.preprocess(&file_name, &page)?),
-            workspace: workspace_outline,
-            previous_page_code: index
-                .entries
-                .get(page_num.wrapping_sub(1))
-                .map(|e| e.code.clone()),
-            next_page_code: index
-                .entries
-                .get(page_num.wrapping_add(1))
-                .map(|e| e.code.clone()),
-        };
-
-        let out_dir = project_paths.export().join(&index.entries[page_num].code);
-
-        let context = GlobalContext {
-            page: &page_ctx,
-            guide: &guide_ctx,
-        };
-        export_page(&context, &project, &out_dir)?;
-
-        let workspace_dir = out_dir.join("workspace");
-        export_workspace(&repo, &tree, &workspace_dir)?;
-
-        last_rev = Some(rev);
-        page_num += 1;
+    if reference.is_some() || config.variants.is_empty() {
+        let reference = reference.unwrap_or("HEAD");
+        return build_guide(project_paths, &repo, &theme, reference, base_url, mode);
     }
 
+    // the landing page uses the public files at the root of the export
+    // directory and every variant uses its own
+    crate::export::export_public_files(project_paths, &theme, mode)?;
+    let guide_ctx = GuideContext {
+        index: Index::default(),
+        base_url: base_url.to_string(),
+        assets: crate::export::fingerprint_public_files(project_paths)?,
+        mode,
+    };
+    for variant in &config.variants {
+        let mut variant_paths = project_paths.clone();
+        variant_paths.set_export(project_paths.export().join(&variant.name));
+        let variant_base_url = crate::page::url_join(base_url, &variant.name);
+
+        build_guide(
+            &variant_paths,
+            &repo,
+            &theme,
+            &variant.reference,
+            &variant_base_url,
+            mode,
+        )
+        .with_context(|| format!("failed to build variant `{}`", &variant.name))?;
+        log::info!(
+            "built variant `{}` from `{}`",
+            &variant.name,
+            &variant.reference
+        );
+    }
+    export_variants_page(project_paths, &config, &theme, &guide_ctx)?;
+    crate::export::export_not_found_page(project_paths, &theme, &guide_ctx)?;
+
     Ok(())
 }
 
-/// Creates a [`Revwalk`](git2::Revwalk) that iterates on reverse.
-///
-/// It iterates by yielding the oldest revisions first.
-fn revwalk(repo: &git2::Repository) -> Result<git2::Revwalk> {
-    let mut revwalk = repo
-        .revwalk()
-        .with_context(|| "failed to create rev walker for repository")?;
-    revwalk.set_sorting(git2::Sort::REVERSE)?;
-    revwalk
-        .push_head()
-        .context("failed to push repository head")?;
-    Ok(revwalk)
+/// Builds the guide found in the history of `reference` into the project's
+/// export directory, with the project's theme `theme`.
+fn build_guide(
+    project_paths: &paths::ProjectPaths, repo: &git2::Repository, theme: &Arc<Theme>,
+    reference: &str, base_url: &str, mode: BuildMode,
+) -> Result<()> {
+    let head = repo
+        .revparse_single(reference)
+        .and_then(|object| object.peel_to_commit())
+        .with_context(|| format!("failed to resolve reference `{}`", reference))?;
+
+    crate::export::export_public_files(project_paths, theme, mode)?;
+    let highlighter = Highlighter::from_project(project_paths.project(), theme)?;
+    let index = guide_index(project_paths.project(), repo, &head)?;
+    let guide_ctx = GuideContext {
+        index: index.clone(),
+        base_url: base_url.to_string(),
+        assets: crate::export::fingerprint_public_files(project_paths)?,
+        mode,
+    };
+    let exported = export_history(project_paths, repo, &head, &guide_ctx, theme, &highlighter)?;
+    crate::export::export_not_found_page(project_paths, theme, &guide_ctx)?;
+
+    if let Some(missing) = index.entries.get(exported..).filter(|m| !m.is_empty()) {
+        log::warn!(
+            "pages listed in the index were not found in the history of `{}`: {}",
+            reference,
+            missing
+                .iter()
+                .map(|entry| entry.code.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+
+    Ok(())
 }
 
-/// Finds the new page in `new_rev` by performing a diff between both revisions.
+/// Returns the index of the guide at `commit`.
 ///
-/// An `old_rev` with a `None` value indicates an empty tree.
+/// The index is read from the commit so that each reference gets its own,
+/// projects that don't commit their index use the one in the working
+/// directory.
+pub fn guide_index(
+    project: &Path, repo: &git2::Repository, commit: &git2::Commit,
+) -> Result<Index> {
+    match Index::from_tree(repo, &commit.tree()?)? {
+        Some(index) => Ok(index),
+        None => Index::from_project(project),
+    }
+}
+
+/// Exports the pages saved in the history of `head` to the project's export
+/// directory, rendered with `theme` and `highlighter`.
 ///
-/// Returns a tuple containing the file name and its contents
-fn find_new_page(
-    repo: &git2::Repository, old_rev: Option<git2::Oid>, new_rev: git2::Oid,
-) -> Result<Option<(String, String)>> {
-    let old_tree = old_rev.and_then(|old_rev| {
-        repo.find_commit(old_rev)
-            .ok()
-            .and_then(|commit| commit.tree().ok())
-    });
-    let new_tree = repo.find_commit(new_rev)?.tree()?;
-
-    let diff = repo.diff_tree_to_tree(old_tree.as_ref(), Some(&new_tree), None)?;
-
-    for delta in diff.deltas() {
-        if delta.status() == git2::Delta::Added {
-            let file = delta.new_file();
-            let file_path = file.path().unwrap();
-
-            if file_path.starts_with("pages") {
-                let page_bytes = new_tree
-                    .get_path(file_path)?
-                    .to_object(repo)?
-                    .as_blob()
-                    .unwrap()
-                    .content()
-                    .to_vec();
-                return Ok(Some((
-                    file_path.display().to_string(),
-                    String::from_utf8(page_bytes).unwrap(),
-                )));
-            }
-        }
+/// The pages must be listed in the same order in the guide's index. Returns
+/// the number of exported pages.
+pub fn export_history(
+    project_paths: &paths::ProjectPaths, repo: &git2::Repository, head: &git2::Commit,
+    guide_ctx: &GuideContext, theme: &Arc<Theme>, highlighter: &Highlighter,
+) -> Result<usize> {
+    let history = history::page_history(repo, head.id())?;
+    for warning in &history.warnings {
+        log::warn!("{}", warning);
+    }
+    history::check_index_order(&guide_ctx.index, &history.pages)?;
+
+    for (page_num, page) in history.pages.iter().enumerate() {
+        let commit = repo.find_commit(page.oid)?;
+        export_saved_page(
+            project_paths,
+            repo,
+            &commit,
+            guide_ctx,
+            theme,
+            highlighter,
+            page_num,
+        )?;
     }
 
-    Ok(None)
+    Ok(history.pages.len())
+}
+
+/// Exports the page number `page_num` in the guide's index, saved in `commit`,
+/// to `<code>/` in the project's export directory.
+///
+/// The page is rendered with `theme` along with the workspace in `commit`,
+/// highlighted by `highlighter`.
+pub fn export_saved_page(
+    project_paths: &paths::ProjectPaths, repo: &git2::Repository, commit: &git2::Commit,
+    guide_ctx: &GuideContext, theme: &Arc<Theme>, highlighter: &Highlighter, page_num: usize,
+) -> Result<()> {
+    let index = &guide_ctx.index;
+    let code = &index.entries[page_num].code;
+    let preprocessor = PagePreprocessor::new(project_paths.project(), theme, guide_ctx);
+
+    let file_name = history::page_path(code).display().to_string();
+    let page = read_page(repo, commit, code)?;
+
+    let tree = commit.tree()?;
+    let workspace_outline =
+        build_workspace_outline(repo, &tree).context("failed to build workspace outline")?;
+    let page_ctx = PageContext {
+        number: page_num,
+        title: crate::page::extract_title(&page),
+        code: code.clone(),
+        content: crate::page::markdown_to_html(&preprocessor.preprocess(&file_name, &page)?),
+        date: commit.time().seconds(),
+        workspace: workspace_outline,
+        previous_page_code: index
+            .entries
+            .get(page_num.wrapping_sub(1))
+            .map(|e| e.code.clone()),
+        next_page_code: index
+            .entries
+            .get(page_num.wrapping_add(1))
+            .map(|e| e.code.clone()),
+    };
+
+    let out_dir = project_paths.export().join(code);
+
+    let context = GlobalContext {
+        page: &page_ctx,
+        guide: guide_ctx,
+    };
+    export_page(&context, project_paths.project(), theme, &out_dir)?;
+
+    let workspace_dir = out_dir.join("workspace");
+    export_workspace(repo, &tree, &workspace_dir, highlighter, guide_ctx.mode)?;
+
+    Ok(())
+}
+
+/// Exports the page that lists the guide's variants to `index.html` in the
+/// export directory.
+///
+/// `guide_ctx` is the context of the guide that holds the variants.
+fn export_variants_page(
+    project_paths: &paths::ProjectPaths, config: &Config, theme: &Arc<Theme>,
+    guide_ctx: &GuideContext,
+) -> Result<()> {
+    let context = VariantsContext {
+        title: config.title.clone(),
+        base_url: guide_ctx.base_url.clone(),
+        variants: config
+            .variants
+            .iter()
+            .map(|variant| VariantEntry {
+                name: variant.name.clone(),
+                title: variant
+                    .title
+                    .clone()
+                    .unwrap_or_else(|| variant.name.clone()),
+            })
+            .collect(),
+    };
+
+    let templates = crate::page::read_theme_templates(project_paths.project(), theme, guide_ctx)
+        .context("failed to read templates")?;
+    let output_html = templates
+        .get_template("variants.html")
+        .context("the theme doesn't provide `variants.html`")?
+        .render(&context)
+        .context("failed to render template")?;
+
+    let out_path = project_paths.export().join("index.html");
+    std::fs::write(&out_path, guide_ctx.mode.html(output_html))
+        .with_context(|| format!("failed to write to {:?}", &out_path))?;
+
+    Ok(())
+}
+
+/// Reads the contents of the page with the given `code` in `commit`.
+fn read_page(repo: &git2::Repository, commit: &git2::Commit, code: &str) -> Result<String> {
+    let page_path = history::page_path(code);
+    let object = commit
+        .tree()?
+        .get_path(&page_path)?
+        .to_object(repo)
+        .with_context(|| format!("failed to read page {:?}", &page_path))?;
+    let blob = object
+        .as_blob()
+        .ok_or_else(|| anyhow::anyhow!("page {:?} is not a file", &page_path))?;
+    String::from_utf8(blob.content().to_vec())
+        .with_context(|| format!("page {:?} is not valid utf-8", &page_path))
 }
 
 /// Exports the whole workspace in the given `tree` to `workspace`.
@@ -151,8 +377,11 @@ fn find_new_page(
 /// revision.
 ///
 /// This will highlight the exported files that are supported by the
-/// highlighting engine.
-fn export_workspace(repo: &git2::Repository, tree: &git2::Tree, workspace: &Path) -> Result<()> {
+/// highlighting engine, and minify them in production.
+fn export_workspace(
+    repo: &git2::Repository, tree: &git2::Tree, workspace: &Path, highlighter: &Highlighter,
+    mode: BuildMode,
+) -> Result<()> {
     std::fs::create_dir_all(workspace)
         .with_context(|| format!("failed to create directory {:?}", workspace))?;
 
@@ -162,7 +391,7 @@ fn export_workspace(repo: &git2::Repository, tree: &git2::Tree, workspace: &Path
             && entry.to_object(repo).unwrap().kind() == Some(git2::ObjectType::Blob)
         {
             let relative_path = path.strip_prefix("workspace").unwrap();
-            let mut out_path = workspace.join(&relative_path);
+            let mut out_path = workspace.join(relative_path);
 
             let new_ext = if let Some(ext) = out_path.extension() {
                 let mut new_ext = ext.to_os_string();
@@ -181,11 +410,10 @@ fn export_workspace(repo: &git2::Repository, tree: &git2::Tree, workspace: &Path
                 std::fs::write(&out_path, "BINARY FILE").unwrap();
             } else {
                 let content_unsafe = String::from_utf8(blob.content().to_vec()).unwrap();
-                let ext = relative_path.extension().unwrap_or_default();
-                let content = crate::code::escape_and_highlight(
-                    &content_unsafe,
-                    ext.to_string_lossy().as_ref(),
-                );
+                let mut content = highlighter.escape_and_highlight(&content_unsafe, relative_path);
+                if mode == BuildMode::Production {
+                    content = crate::minify::preformatted_html(&content);
+                }
 
                 std::fs::write(&out_path, &content).unwrap();
             }
@@ -197,20 +425,22 @@ fn export_workspace(repo: &git2::Repository, tree: &git2::Tree, workspace: &Path
 }
 
 /// Exports the page with the given contexts to `out_dir/index.html`
-fn export_page(ctx: &GlobalContext, project: &Path, out_dir: &Path) -> Result<()> {
-    std::fs::create_dir_all(&out_dir)
+fn export_page(
+    ctx: &GlobalContext, project: &Path, theme: &Arc<Theme>, out_dir: &Path,
+) -> Result<()> {
+    std::fs::create_dir_all(out_dir)
         .with_context(|| format!("failed to create dir {:?}", &out_dir))?;
 
-    let templates =
-        crate::page::read_theme_templates(project).context("failed to read templates")?;
+    let templates = crate::page::read_theme_templates(project, theme, ctx.guide)
+        .context("failed to read templates")?;
 
     let output_html = templates
         .get_template("template.html")?
-        .render(&ctx)
+        .render(ctx)
         .context("failed to render template")?;
 
     let out_path = out_dir.join("index.html");
-    std::fs::write(&out_path, output_html)
+    std::fs::write(&out_path, ctx.guide.mode.html(output_html))
         .with_context(|| format!("failed to write to {:?}", &out_path))?;
 
     Ok(())
@@ -253,3 +483,39 @@ fn build_workspace_outline(repo: &git2::Repository, tree: &git2::Tree) -> Result
 
     Ok(ws_builder.finish())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{index, TestRepo};
+
+    #[test]
+    fn rebased_history_is_exported() {
+        let test = TestRepo::project();
+        let base = test.commit(&[], "base", &[("workspace/main.rs", "fn main() {}")]);
+        let one = test.commit(&[base], "one", &[("pages/one.md", "# One")]);
+        let two = test.commit(&[one], "two", &[("pages/two.md", "# Two")]);
+
+        // `two` and `one` rebased on top of another commit
+        let upstream = test.commit(&[base], "upstream", &[("workspace/lib.rs", "")]);
+        let one = test.commit(&[upstream], "one", &[("pages/one.md", "# One")]);
+        let two_rebased = test.commit(&[one], "two", &[("pages/two.md", "# Two")]);
+        assert_ne!(two, two_rebased);
+        test.set_head(two_rebased);
+
+        index(&["one", "two"])
+            .write_to_project(test.path())
+            .unwrap();
+
+        let project_paths = paths::ProjectPaths::from_project(test.path().to_path_buf());
+        build(&project_paths, None, "/", BuildMode::Development).unwrap();
+
+        let export = project_paths.export();
+        let one_html = std::fs::read_to_string(export.join("one/index.html")).unwrap();
+        assert!(one_html.contains("One"));
+        assert!(export.join("two/index.html").is_file());
+        // the workspace of the rebased pages has the upstream changes
+        assert!(export.join("one/workspace/lib.rs.html").is_file());
+        assert!(export.join("two/workspace/main.rs.html").is_file());
+    }
+}
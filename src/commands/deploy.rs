@@ -0,0 +1,287 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use clap::Parser;
+
+use crate::config::Config;
+use crate::{history, paths};
+
+#[derive(Parser)]
+pub struct Opts {
+    /// Directory with the built guide.
+    ///
+    /// By default, it is the project's export directory.
+    #[clap(long)]
+    dir: Option<PathBuf>,
+
+    /// Path the built guide is copied to.
+    #[clap(long, conflicts_with = "branch", required_unless_present = "branch")]
+    path: Option<PathBuf>,
+
+    /// Replaces the contents of `--path` if it isn't empty.
+    #[clap(long, requires = "path")]
+    force: bool,
+
+    /// Branch the built guide is committed to, e.g. `gh-pages`.
+    ///
+    /// The branch holds only the built guide and it must not be checked out.
+    #[clap(long)]
+    branch: Option<String>,
+
+    /// Repository that holds `--branch`, it can be bare.
+    ///
+    /// By default, it is the guide's repository.
+    #[clap(long, requires = "branch")]
+    repo: Option<PathBuf>,
+}
+
+pub fn execute(opts: &Opts) -> Result<()> {
+    let project_paths = paths::ProjectPaths::new()?;
+    let dir = opts
+        .dir
+        .clone()
+        .unwrap_or_else(|| project_paths.export().clone());
+    anyhow::ensure!(
+        dir.is_dir(),
+        "there is no built guide at {:?}, run `codasai build` first",
+        &dir
+    );
+
+    if let Some(path) = &opts.path {
+        deploy_to_path(&dir, path, opts.force)?;
+        log::info!("deployed {:?} to {:?}", &dir, path);
+    } else if let Some(branch) = &opts.branch {
+        let repo_path = opts.repo.as_ref().unwrap_or(project_paths.project());
+        let config = Config::from_project(project_paths.project())?;
+        deploy_to_branch(&project_paths, &config, &dir, repo_path, branch)?;
+    }
+
+    Ok(())
+}
+
+/// Copies the built guide in `dir` to `dest`.
+///
+/// `dest` must be empty unless `force` is set, in which case its contents are
+/// replaced. A `.git` directory in `dest` is kept. `dest` can't contain `dir`,
+/// like the project's directory does, since replacing its contents would
+/// remove the guide.
+fn deploy_to_path(dir: &Path, dest: &Path, force: bool) -> Result<()> {
+    if dest.exists() {
+        let canonical_dir = dir
+            .canonicalize()
+            .with_context(|| format!("failed to canonicalize {:?}", dir))?;
+        let canonical_dest = dest
+            .canonicalize()
+            .with_context(|| format!("failed to canonicalize {:?}", dest))?;
+        anyhow::ensure!(
+            !canonical_dir.starts_with(&canonical_dest),
+            "the built guide can't be deployed to {:?}, which contains it",
+            dest
+        );
+        anyhow::ensure!(
+            !canonical_dest.starts_with(&canonical_dir),
+            "the built guide can't be deployed to {:?}, which is inside of it",
+            dest
+        );
+
+        let entries = std::fs::read_dir(dest)
+            .with_context(|| format!("failed to read directory {:?}", dest))?
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| format!("failed to read directory {:?}", dest))?;
+        if !entries.is_empty() {
+            anyhow::ensure!(
+                force,
+                "{:?} is not empty, use `--force` to replace its contents",
+                dest
+            );
+        }
+
+        for entry in entries.iter().filter(|entry| entry.file_name() != ".git") {
+            let path = entry.path();
+            if path.is_dir() {
+                std::fs::remove_dir_all(&path)
+            } else {
+                std::fs::remove_file(&path)
+            }
+            .with_context(|| format!("failed to remove {:?}", &path))?;
+        }
+    }
+
+    std::fs::create_dir_all(dest)
+        .with_context(|| format!("failed to create directory {:?}", dest))?;
+    crate::export::copy_dir_contents(dir, dest)
+}
+
+/// Commits the built guide in `dir` to `branch` in the repository at
+/// `repo_path`.
+///
+/// The commit's only parent is the previous deployment, if any, and nothing is
+/// committed if the built guide didn't change.
+fn deploy_to_branch(
+    project_paths: &paths::ProjectPaths, config: &Config, dir: &Path, repo_path: &Path,
+    branch: &str,
+) -> Result<()> {
+    let repo = git2::Repository::open(repo_path)
+        .with_context(|| format!("failed to open repository at {:?}", repo_path))?;
+    let refname = format!("refs/heads/{}", branch);
+    anyhow::ensure!(
+        git2::Reference::is_valid_name(&refname),
+        "`{}` is not a valid branch name",
+        branch
+    );
+
+    // the checked out files would get out of sync with the branch
+    if !repo.is_bare() {
+        if let Ok(head) = repo.head() {
+            anyhow::ensure!(
+                head.name() != Some(refname.as_str()),
+                "`{}` is checked out in {:?}, check out another branch first",
+                branch,
+                repo_path
+            );
+        }
+    }
+
+    let tree = history::tree_from_dir(&repo, dir)?
+        .ok_or_else(|| anyhow::anyhow!("there are no files to deploy in {:?}", dir))?;
+    let tree = repo.find_tree(tree)?;
+
+    let parent = match repo.find_reference(&refname) {
+        Ok(reference) => Some(
+            reference
+                .peel_to_commit()
+                .with_context(|| format!("`{}` doesn't point to a commit", branch))?,
+        ),
+        Err(e) if e.code() == git2::ErrorCode::NotFound => None,
+        Err(e) => return Err(e).with_context(|| format!("failed to read branch `{}`", branch)),
+    };
+    if parent.as_ref().map(|parent| parent.tree_id()) == Some(tree.id()) {
+        log::info!("`{}` is already up to date", branch);
+        return Ok(());
+    }
+
+    let message = match guide_head(project_paths.project()) {
+        Some(head) => format!("Deploy guide at {}", head),
+        None => String::from("Deploy guide"),
+    };
+    let committer = config.save.committer_signature()?;
+    let commit = repo.commit(
+        Some(&refname),
+        &committer,
+        &committer,
+        &message,
+        &tree,
+        &parent.iter().collect::<Vec<_>>(),
+    )?;
+    log::info!("deployed {:?} to `{}` in commit {}", dir, branch, commit);
+
+    Ok(())
+}
+
+/// Returns the id of the guide's `HEAD` commit, if any.
+fn guide_head(project: &Path) -> Option<git2::Oid> {
+    let repo = git2::Repository::open(project).ok()?;
+    let head = repo.head().ok()?.peel_to_commit().ok()?;
+    Some(head.id())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{dir_with_files, TestRepo};
+
+    #[test]
+    fn deploys_to_branch_of_bare_repo() {
+        let project = TestRepo::project();
+        let project_paths = paths::ProjectPaths::from_project(project.path().to_path_buf());
+        let config = Config::from_project(project.path()).unwrap();
+        let remote = tempfile::tempdir().unwrap();
+        let repo = git2::Repository::init_bare(remote.path()).unwrap();
+        let branch_commit = || {
+            repo.find_reference("refs/heads/gh-pages")
+                .unwrap()
+                .peel_to_commit()
+                .unwrap()
+        };
+
+        let guide = dir_with_files(&[("index.html", "one"), ("one/index.html", "one")]);
+        deploy_to_branch(
+            &project_paths,
+            &config,
+            guide.path(),
+            remote.path(),
+            "gh-pages",
+        )
+        .unwrap();
+        let first = branch_commit();
+        assert_eq!(first.parent_count(), 0);
+        let tree = first.tree().unwrap();
+        assert!(tree.get_path(Path::new("index.html")).is_ok());
+        assert!(tree.get_path(Path::new("one/index.html")).is_ok());
+
+        let guide = dir_with_files(&[("index.html", "two"), ("two/index.html", "two")]);
+        deploy_to_branch(
+            &project_paths,
+            &config,
+            guide.path(),
+            remote.path(),
+            "gh-pages",
+        )
+        .unwrap();
+        let second = branch_commit();
+        assert_eq!(second.parent_ids().collect::<Vec<_>>(), [first.id()]);
+        let tree = second.tree().unwrap();
+        assert!(tree.get_path(Path::new("one/index.html")).is_err());
+        assert!(tree.get_path(Path::new("two/index.html")).is_ok());
+
+        // deploying the same files again doesn't add a commit
+        deploy_to_branch(
+            &project_paths,
+            &config,
+            guide.path(),
+            remote.path(),
+            "gh-pages",
+        )
+        .unwrap();
+        assert_eq!(branch_commit().id(), second.id());
+    }
+
+    #[test]
+    fn deploy_to_path_requires_force_to_replace_files() {
+        let guide = dir_with_files(&[("index.html", "guide")]);
+        let dest = dir_with_files(&[("old.html", "old"), (".git/HEAD", "ref")]);
+
+        let error = deploy_to_path(guide.path(), dest.path(), false).unwrap_err();
+        assert!(error.to_string().contains("use `--force`"));
+        assert!(dest.path().join("old.html").exists());
+        assert!(!dest.path().join("index.html").exists());
+
+        deploy_to_path(guide.path(), dest.path(), true).unwrap();
+        assert!(!dest.path().join("old.html").exists());
+        assert!(dest.path().join(".git/HEAD").exists());
+        assert_eq!(
+            std::fs::read_to_string(dest.path().join("index.html")).unwrap(),
+            "guide"
+        );
+    }
+
+    #[test]
+    fn deploy_to_path_rejects_directories_around_the_guide() {
+        let project = TestRepo::project();
+        let project_paths = paths::ProjectPaths::from_project(project.path().to_path_buf());
+        std::fs::create_dir_all(project_paths.export()).unwrap();
+        std::fs::write(project_paths.export().join("index.html"), "guide").unwrap();
+
+        for dest in [
+            project.path().to_path_buf(),
+            project_paths.codasai().clone(),
+            project_paths.export().clone(),
+            project_paths.export().join("nested"),
+        ] {
+            std::fs::create_dir_all(&dest).unwrap();
+            assert!(deploy_to_path(project_paths.export(), &dest, true).is_err());
+        }
+        assert!(project_paths.pages().is_dir());
+        assert!(project_paths.export().join("index.html").is_file());
+    }
+}
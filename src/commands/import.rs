@@ -0,0 +1,300 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use clap::Parser;
+
+use crate::config::Config;
+use crate::context::{Index, IndexEntry};
+use crate::history;
+
+#[derive(Parser)]
+pub struct Opts {
+    /// Path to the git repository to import.
+    repo: PathBuf,
+    /// Commits that become pages, e.g. `v1.0..main`.
+    ///
+    /// A single revision selects it and all of its ancestors. By default, all
+    /// the commits reachable from the repository's `HEAD` are selected. Only
+    /// first-parent history is followed.
+    #[clap(long)]
+    range: Option<String>,
+    /// Title of the guide.
+    ///
+    /// By default, it is the name of the imported repository's directory.
+    #[clap(long)]
+    title: Option<String>,
+    /// Path to the directory that will contain the project.
+    ///
+    /// Must be empty.
+    #[clap(short, long, default_value = "./")]
+    path: PathBuf,
+}
+
+pub fn execute(opts: &Opts) -> Result<()> {
+    let source = git2::Repository::open(&opts.repo)
+        .with_context(|| format!("failed to open repository at {:?}", &opts.repo))?;
+    let commits = select_commits(&source, opts.range.as_deref())?;
+    anyhow::ensure!(!commits.is_empty(), "there are no commits to import");
+
+    let title = match &opts.title {
+        Some(title) => title.clone(),
+        None => source
+            .workdir()
+            .unwrap_or_else(|| source.path())
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| String::from("Untitled")),
+    };
+
+    import_commits(&source, &commits, &title, &opts.path)?;
+
+    Ok(())
+}
+
+/// Creates a project titled `title` at `path` with a page for each of
+/// `commits` of `source`.
+///
+/// Every page is titled by its commit's summary, its text is the commit's body
+/// and its workspace is the commit's tree. Returns the project's directory.
+fn import_commits(
+    source: &git2::Repository, commits: &[git2::Oid], title: &str, path: &Path,
+) -> Result<PathBuf> {
+    let project = crate::commands::init::init_project(title, path)?;
+    let repo = git2::Repository::open(&project)
+        .with_context(|| format!("failed to open repository at {:?}", &project))?;
+    let config = Config::from_project(&project)?;
+    let committer = config.save.committer_signature()?;
+
+    // the project's scaffolding goes in its own commit so that every following
+    // commit only adds a page
+    let mut git_index = repo.index().context("failed to read git index")?;
+    git_index.add_all(["*"], git2::IndexAddOption::DEFAULT, None)?;
+    git_index.write().context("failed to write git index")?;
+    let mut tree = repo.find_tree(git_index.write_tree()?)?;
+    let mut parent = repo.commit(
+        Some("HEAD"),
+        &committer,
+        &committer,
+        "Initialize guide",
+        &tree,
+        &[],
+    )?;
+
+    let mut index = Index::from_project(&project)?;
+    let mut copied = HashMap::new();
+    for &oid in commits {
+        let commit = source.find_commit(oid)?;
+        let message = String::from_utf8_lossy(commit.message_bytes()).into_owned();
+        let (summary, body) = split_message(&message);
+
+        let code =
+            crate::commands::new::unique_code(&project, &index, &crate::page::slugify(summary));
+        let page = if body.is_empty() {
+            format!("# {}\n", escape_template(summary))
+        } else {
+            format!(
+                "# {}\n\n{}\n",
+                escape_template(summary),
+                escape_template(body)
+            )
+        };
+        let page_path = history::page_path(&code);
+        // written right away so that the following codes are unique
+        std::fs::write(project.join(&page_path), &page)
+            .with_context(|| format!("failed to write to {:?}", &page_path))?;
+
+        index.entries.push(IndexEntry {
+            name: summary.to_string(),
+            code: code.clone(),
+        });
+
+        let workspace = copy_tree(source, &commit.tree()?, &repo, &mut copied)
+            .with_context(|| format!("failed to copy tree of commit {}", oid))?;
+        let new_tree = history::tree_with_entry(
+            &repo,
+            Some(&tree),
+            Path::new("workspace"),
+            Some((workspace, i32::from(git2::FileMode::Tree))),
+        )?;
+        let page_blob = repo.blob(page.as_bytes())?;
+        let new_tree = history::tree_with_entry(
+            &repo,
+            Some(&repo.find_tree(new_tree)?),
+            &page_path,
+            Some((page_blob, i32::from(git2::FileMode::Blob))),
+        )?;
+        let index_blob = repo.blob(toml::to_string_pretty(&index)?.as_bytes())?;
+        let new_tree = history::tree_with_entry(
+            &repo,
+            Some(&repo.find_tree(new_tree)?),
+            Path::new(history::INDEX_PATH),
+            Some((index_blob, i32::from(git2::FileMode::Blob))),
+        )?;
+        tree = repo.find_tree(new_tree)?;
+
+        parent = repo.commit(
+            Some("HEAD"),
+            &commit.author(),
+            &committer,
+            &history::page_commit_message(summary, &code),
+            &tree,
+            &[&repo.find_commit(parent)?],
+        )?;
+
+        log::info!("imported commit {} as page `{}`", oid, &code);
+    }
+
+    git_index.read_tree(&tree)?;
+    git_index.write().context("failed to write git index")?;
+    repo.checkout_head(Some(git2::build::CheckoutBuilder::new().force()))
+        .context("failed to check out the imported guide")?;
+
+    Ok(project)
+}
+
+/// Selects the commits in `range` following first-parent history.
+///
+/// The oldest commit comes first.
+fn select_commits(repo: &git2::Repository, range: Option<&str>) -> Result<Vec<git2::Oid>> {
+    let resolve = |rev: &str| -> Result<git2::Oid> {
+        let commit = repo
+            .revparse_single(rev)
+            .and_then(|object| object.peel_to_commit())
+            .with_context(|| format!("failed to resolve revision `{}`", rev))?;
+        Ok(commit.id())
+    };
+
+    let (base, head) = match range {
+        Some(range) => match range.split_once("..") {
+            Some((base, head)) => (
+                Some(resolve(base)?),
+                resolve(if head.is_empty() { "HEAD" } else { head })?,
+            ),
+            None => (None, resolve(range)?),
+        },
+        None => (None, resolve("HEAD")?),
+    };
+
+    history::first_parent_chain_from(repo, head, base)
+}
+
+/// Splits a commit message into its summary and body.
+fn split_message(message: &str) -> (&str, &str) {
+    let message = message.trim();
+    match message.split_once('\n') {
+        Some((summary, body)) => (summary.trim(), body.trim()),
+        None => (message, ""),
+    }
+}
+
+/// Escapes the template delimiters in `text` so that pages show it as it is.
+///
+/// Every `{{`, `{%` and `{#` is written by an expression that prints it.
+fn escape_template(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match (ch, chars.peek()) {
+            ('{', Some(&next @ ('{' | '%' | '#'))) => {
+                chars.next();
+                escaped.push_str(&format!("{{{{ \"{{{}\" }}}}", next));
+            },
+            _ => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+/// Copies `tree` and all the objects it references from `source` to `dest`.
+///
+/// `copied` caches the trees already copied. Submodules are skipped.
+fn copy_tree(
+    source: &git2::Repository, tree: &git2::Tree, dest: &git2::Repository,
+    copied: &mut HashMap<git2::Oid, git2::Oid>,
+) -> Result<git2::Oid> {
+    if let Some(oid) = copied.get(&tree.id()) {
+        return Ok(*oid);
+    }
+
+    let mut builder = dest.treebuilder(None)?;
+    for entry in tree.iter() {
+        let name = entry.name_bytes();
+        let oid = match entry.kind() {
+            Some(git2::ObjectType::Tree) => {
+                let subtree = entry.to_object(source)?.peel_to_tree()?;
+                copy_tree(source, &subtree, dest, copied)?
+            },
+            Some(git2::ObjectType::Blob) => {
+                if dest.odb()?.exists(entry.id()) {
+                    entry.id()
+                } else {
+                    dest.blob(entry.to_object(source)?.peel_to_blob()?.content())?
+                }
+            },
+            _ => {
+                log::warn!(
+                    "skipping submodule {:?}",
+                    String::from_utf8_lossy(entry.name_bytes())
+                );
+                continue;
+            },
+        };
+        builder.insert(name, oid, entry.filemode())?;
+    }
+
+    let oid = builder.write()?;
+    copied.insert(tree.id(), oid);
+    Ok(oid)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::context::GuideContext;
+    use crate::export::{AssetManifest, BuildMode};
+    use crate::testing::TestRepo;
+    use crate::theme::Theme;
+
+    #[test]
+    fn commit_messages_are_imported_as_they_are() {
+        let source = TestRepo::new();
+        let first = source.commit(&[], "Add main", &[("main.rs", "fn main() {}")]);
+        let message = "Print {{ name }}\n\nUses `{% raw %}`, `{# #}` and `{% endraw %}`.";
+        let second = source.commit(&[first], message, &[("main.rs", "fn main() { 1 }")]);
+
+        let dest = tempfile::tempdir().unwrap();
+        let project = import_commits(&source.repo, &[first, second], "Guide", dest.path()).unwrap();
+
+        let index = Index::from_project(&project).unwrap();
+        let names = index
+            .entries
+            .iter()
+            .map(|entry| &entry.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["Add main", "Print {{ name }}"]);
+        let code = &index.entries[1].code;
+        assert_eq!(
+            std::fs::read_to_string(project.join("workspace/main.rs")).unwrap(),
+            "fn main() { 1 }"
+        );
+
+        let theme = Arc::new(Theme::from_project(&project).unwrap());
+        let guide_context = GuideContext {
+            base_url: "/".to_string(),
+            index: Index::default(),
+            assets: AssetManifest::default(),
+            mode: BuildMode::Development,
+        };
+        let page = std::fs::read_to_string(project.join(history::page_path(code))).unwrap();
+        let rendered = crate::page::PagePreprocessor::new(&project, &theme, &guide_context)
+            .preprocess("page.md", &page)
+            .unwrap();
+        assert_eq!(
+            rendered,
+            "# Print {{ name }}\n\nUses `{% raw %}`, `{# #}` and `{% endraw %}`."
+        );
+    }
+}
@@ -1,11 +1,10 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use anyhow::{Context, Result};
 use clap::Parser;
 
 use crate::context::Index;
-
-static THEME_DIR: include_dir::Dir<'_> = include_dir::include_dir!("runtime/theme");
+use crate::theme::InstalledTheme;
 
 #[derive(Parser)]
 pub struct Opts {
@@ -16,16 +15,39 @@ pub struct Opts {
     /// Must be empty.
     #[clap(short, long, default_value = "./")]
     path: PathBuf,
+    /// Installed theme the guide's theme inherits from.
+    ///
+    /// It is either a directory or a `.tar`, `.tar.gz` or `.tgz` archive with
+    /// a `theme.toml` manifest.
+    #[clap(long)]
+    theme: Option<PathBuf>,
 }
 
 pub fn execute(opts: &Opts) -> Result<()> {
-    std::fs::create_dir_all(&opts.path)
-        .with_context(|| format!("failed to create directory {:?}", &opts.path))?;
+    // the theme is checked first so that no project is left behind if it is
+    // invalid
+    let theme = match &opts.theme {
+        Some(theme) => Some(check_theme(&opts.path, theme)?),
+        None => None,
+    };
+
+    let project = init_project(&opts.title, &opts.path)?;
+    if let Some(theme) = theme {
+        set_theme(&project, &theme)?;
+    }
+    Ok(())
+}
 
-    let path = opts
-        .path
+/// Initializes a codasai project titled `title` in `path`.
+///
+/// Returns the canonicalized path of the project.
+pub fn init_project(title: &str, path: &Path) -> Result<PathBuf> {
+    std::fs::create_dir_all(path)
+        .with_context(|| format!("failed to create directory {:?}", path))?;
+
+    let path = path
         .canonicalize()
-        .with_context(|| format!("failed to canonicalize path {:?}", &opts.path))?;
+        .with_context(|| format!("failed to canonicalize path {:?}", path))?;
 
     git2::Repository::init(&path)
         .with_context(|| format!("failed to initialize git repository in {:?} ", &path))?;
@@ -34,35 +56,139 @@ pub fn execute(opts: &Opts) -> Result<()> {
     std::fs::create_dir_all(&dotcodasai)
         .with_context(|| format!("failed to create .codasai directory in {:?}", &dotcodasai))?;
 
-    let guide_toml = dotcodasai.join("guide.toml");
-    let title = escape_toml_string(&opts.title);
-    std::fs::write(&guide_toml, format!("title = \"{}\"", title))?;
+    let mut config = toml::value::Table::new();
+    config.insert("title".to_string(), title.into());
+    write_config(&path, config)?;
 
     std::fs::create_dir(path.join("static")).context("failed to create `static/` directory")?;
     std::fs::create_dir(path.join("workspace"))
         .context("failed to create `workspace/` directory")?;
     std::fs::create_dir(path.join("pages")).context("failed to create `pages/` directory")?;
 
-    THEME_DIR
-        .extract(path.join(".codasai/theme"))
-        .context("failed to extract default theme to `.codasai/theme/`")?;
+    // the built-in theme is used for the files the project's theme doesn't
+    // override
+    std::fs::create_dir(dotcodasai.join("theme"))
+        .context("failed to create `.codasai/theme/` directory")?;
+
+    std::fs::write(
+        dotcodasai.join("page-template.md"),
+        super::new::DEFAULT_PAGE_TEMPLATE,
+    )
+    .context("failed to create page template")?;
 
     let default_index = toml::to_string_pretty(&Index::default()).unwrap();
     std::fs::write(dotcodasai.join("index.toml"), &default_index)
         .context("failed to create index file")?;
 
-    Ok(())
+    Ok(path)
 }
 
-fn escape_toml_string(s: &str) -> String {
-    let mut escaped = String::new();
-    for ch in s.chars() {
-        match ch {
-            ch if ch >= 0 as char && ch <= 0x1f as char => escaped.extend(ch.escape_default()),
-            '"' => escaped.push_str("\\\""),
-            '\\' => escaped.push_str("\\\\"),
-            ch => escaped.push(ch),
+/// Checks that the installed theme at `theme` is valid for the project at
+/// `project`.
+///
+/// Returns the canonicalized path of the theme.
+fn check_theme(project: &Path, theme: &Path) -> Result<PathBuf> {
+    let theme = theme
+        .canonicalize()
+        .with_context(|| format!("failed to canonicalize path {:?}", theme))?;
+    let installed = InstalledTheme::open(project, &theme)?;
+
+    let manifest = installed.manifest();
+    log::info!("using theme {} {}", &manifest.name, &manifest.version);
+    Ok(theme)
+}
+
+/// Makes the project at `project` inherit the installed theme at `theme`.
+///
+/// The theme's path is written relative to the project, so that the project
+/// can be moved along with its theme.
+fn set_theme(project: &Path, theme: &Path) -> Result<()> {
+    let guide_toml = project.join(".codasai/guide.toml");
+    let config = std::fs::read_to_string(&guide_toml)
+        .with_context(|| format!("failed to read config file {:?}", &guide_toml))?;
+    let mut config: toml::value::Table = toml::from_str(&config)
+        .with_context(|| format!("failed to deserialize config at {:?}", &guide_toml))?;
+
+    let theme_path = relative_path(project, theme);
+    let theme_path = theme_path
+        .to_str()
+        .ok_or_else(|| anyhow::anyhow!("theme path {:?} isn't valid UTF-8", theme))?;
+    let mut theme_config = toml::value::Table::new();
+    theme_config.insert("path".to_string(), theme_path.into());
+    config.insert("theme".to_string(), theme_config.into());
+
+    write_config(project, config)
+}
+
+/// Writes `config` to the `guide.toml` of the project at `project`.
+fn write_config(project: &Path, config: toml::value::Table) -> Result<()> {
+    let guide_toml = project.join(".codasai/guide.toml");
+    // values are only emitted before tables when serializing a `Value`
+    let config =
+        toml::to_string(&toml::Value::Table(config)).context("failed to serialize config")?;
+    std::fs::write(&guide_toml, config)
+        .with_context(|| format!("failed to write to {:?}", &guide_toml))
+}
+
+/// Returns the path of `path` relative to the directory `base`, both
+/// canonicalized.
+///
+/// `path` is returned as it is if it isn't on the same drive as `base`.
+fn relative_path(base: &Path, path: &Path) -> PathBuf {
+    let base_components = base.components().collect::<Vec<_>>();
+    let components = path.components().collect::<Vec<_>>();
+    let common = base_components
+        .iter()
+        .zip(&components)
+        .take_while(|(base, component)| base == component)
+        .count();
+    if common == 0 {
+        return path.to_path_buf();
+    }
+
+    let mut relative = PathBuf::new();
+    for _ in common..base_components.len() {
+        relative.push("..");
+    }
+    relative.extend(&components[common..]);
+    if relative.as_os_str().is_empty() {
+        relative.push(".");
+    }
+    relative
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn relative_paths() {
+        let project = Path::new("/home/user/guide");
+        for (path, expected) in [
+            ("/home/user/guide/themes/dark", "themes/dark"),
+            ("/home/user/themes/dark.tar.gz", "../themes/dark.tar.gz"),
+            ("/opt/themes/dark", "../../../opt/themes/dark"),
+            ("/home/user/guide", "."),
+        ] {
+            assert_eq!(relative_path(project, Path::new(path)), Path::new(expected));
         }
     }
-    escaped
+
+    #[test]
+    fn config_is_valid_toml() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir_path = dir.path().canonicalize().unwrap();
+        let title = "A \"guide\" with \\ and \u{1b}[1m control\ncharacters";
+        let project = init_project(title, &dir_path.join("guide")).unwrap();
+        let theme = dir_path.join("themes/it's \"dark\"");
+        set_theme(&project, &theme).unwrap();
+
+        let config = Config::from_project(&project).unwrap();
+        assert_eq!(config.title, title);
+        assert_eq!(
+            config.theme.path.as_deref(),
+            Some(Path::new("../themes/it's \"dark\""))
+        );
+    }
 }
@@ -0,0 +1,330 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use clap::Parser;
+
+use crate::config::Config;
+use crate::context::{Index, IndexEntry};
+use crate::history;
+
+#[derive(Parser)]
+pub struct Opts {
+    /// Branch whose pages are integrated into the current branch.
+    branch: String,
+}
+
+pub fn execute(opts: &Opts) -> Result<()> {
+    let project = crate::paths::project()
+        .context("current directory is not part of a Codasai project")?
+        .canonicalize()
+        .context("failed to canonicalize project directory")?;
+
+    let config = Config::from_project(&project)?;
+    let committer = config.save.committer_signature()?;
+    let repo = git2::Repository::open(&project)
+        .with_context(|| format!("failed to open repository at {:?}", &project))?;
+
+    integrate_branch(&repo, &committer, &opts.branch)
+}
+
+/// Saves the pages of `branch` since it diverged from `HEAD`, in order, after
+/// the pages of `HEAD`.
+///
+/// The index is rebuilt so that it lists the pages in their new order. If the
+/// changes of any page conflict with `HEAD`, nothing is integrated and the
+/// conflicting files of every page are reported.
+fn integrate_branch(
+    repo: &git2::Repository, committer: &git2::Signature, branch: &str,
+) -> Result<()> {
+    let head = repo
+        .head()
+        .context("failed to get repository head")?
+        .peel_to_commit()
+        .context("repository head is not a commit")?;
+    let branch_tip = repo
+        .revparse_single(branch)
+        .and_then(|object| object.peel_to_commit())
+        .with_context(|| format!("failed to resolve branch `{}`", branch))?;
+    let base = repo
+        .merge_base(head.id(), branch_tip.id())
+        .with_context(|| format!("`{}` doesn't share history with `HEAD`", branch))?;
+
+    let commits = history::first_parent_chain_from(repo, branch_tip.id(), Some(base))?;
+    anyhow::ensure!(!commits.is_empty(), "`{}` has nothing to integrate", branch);
+
+    let mut index = history_index(repo, &head)?;
+    let mut integrated = Vec::new();
+    let mut conflicts = Vec::new();
+
+    let mut tip = head;
+    for oid in &commits {
+        let commit = repo.find_commit(*oid)?;
+        let added_pages = history::page_changes(repo, &commit)?.added;
+        let page_name = if added_pages.is_empty() {
+            format!("commit {}", oid)
+        } else {
+            format!("page `{}`", added_pages.join("`, `"))
+        };
+
+        for code in &added_pages {
+            anyhow::ensure!(
+                index.entries.iter().all(|entry| &entry.code != code),
+                "page `{}` from `{}` already exists in the guide",
+                code,
+                branch
+            );
+            index.entries.push(IndexEntry {
+                name: page_title(repo, &commit, code)?,
+                code: code.clone(),
+            });
+        }
+
+        let mut merged = repo
+            .cherrypick_commit(&commit, &tip, 0, None)
+            .with_context(|| format!("failed to apply {}", &page_name))?;
+        // both sides add entries to the index so it is rebuilt instead of merged,
+        // removing it also removes its conflicts
+        merged.remove_path(Path::new(history::INDEX_PATH))?;
+
+        // the branch's side of the conflicts is kept so that the following pages
+        // are applied, and checked for conflicts, on top of this one
+        let mut resolved = Vec::new();
+        for conflict in merged.conflicts()? {
+            let conflict = conflict?;
+            let entry = conflict
+                .our
+                .as_ref()
+                .or(conflict.their.as_ref())
+                .or(conflict.ancestor.as_ref())
+                .context("conflict without entries")?;
+            let path = PathBuf::from(String::from_utf8_lossy(&entry.path).into_owned());
+            let their = conflict.their.map(|their| (their.id, their.mode as i32));
+            resolved.push((path, their));
+        }
+        if !resolved.is_empty() {
+            let paths = resolved
+                .iter()
+                .map(|(path, _)| path.to_string_lossy().into_owned())
+                .collect::<Vec<_>>();
+            conflicts.push((page_name.clone(), paths));
+        }
+        for (path, _) in &resolved {
+            merged.remove_path(path)?;
+        }
+
+        let mut tree = merged.write_tree_to(repo)?;
+        for (path, their) in resolved {
+            tree = history::tree_with_entry(repo, Some(&repo.find_tree(tree)?), &path, their)?;
+        }
+        let tree = history::tree_with_entry(
+            repo,
+            Some(&repo.find_tree(tree)?),
+            Path::new(history::INDEX_PATH),
+            Some((
+                repo.blob(toml::to_string_pretty(&index)?.as_bytes())?,
+                i32::from(git2::FileMode::Blob),
+            )),
+        )?;
+
+        let new_commit = repo.commit(
+            None,
+            &commit.author(),
+            committer,
+            &String::from_utf8_lossy(commit.message_bytes()),
+            &repo.find_tree(tree)?,
+            &[&tip],
+        )?;
+        tip = repo.find_commit(new_commit)?;
+        integrated.push(page_name);
+    }
+
+    if !conflicts.is_empty() {
+        let conflicts = conflicts
+            .iter()
+            .map(|(page_name, paths)| {
+                format!("{} conflicts in:\n    {}", page_name, paths.join("\n    "))
+            })
+            .collect::<Vec<_>>();
+        anyhow::bail!(
+            "`{}` conflicts with the current branch:\n\n{}\n\nnothing was integrated, resolve \
+             the conflicts in `{}` (e.g. by rebasing it) and try again",
+            branch,
+            conflicts.join("\n\n"),
+            branch
+        );
+    }
+
+    repo.checkout_tree(
+        tip.as_object(),
+        Some(git2::build::CheckoutBuilder::new().safe()),
+    )
+    .context("failed to check out the integrated pages")?;
+    history::update_head(repo, tip.id(), &format!("codasai: integrate {}", branch))?;
+
+    for page_name in &integrated {
+        log::info!("integrated {}", page_name);
+    }
+
+    Ok(())
+}
+
+/// Returns an index that lists the pages in the history of `head` in the order
+/// they were saved.
+///
+/// The titles are taken from the index in `head`'s tree, if it lists the page.
+fn history_index(repo: &git2::Repository, head: &git2::Commit) -> Result<Index> {
+    let tracked = Index::from_tree(repo, &head.tree()?)?.unwrap_or_default();
+    let mut index = Index::default();
+    for page in history::page_history(repo, head.id())?.pages {
+        let tracked_name = tracked
+            .entries
+            .iter()
+            .find(|entry| entry.code == page.code)
+            .map(|entry| entry.name.clone());
+        let name = match tracked_name {
+            Some(name) => name,
+            None => page_title(repo, &repo.find_commit(page.oid)?, &page.code)?,
+        };
+        index.entries.push(IndexEntry {
+            name,
+            code: page.code,
+        });
+    }
+    Ok(index)
+}
+
+/// Returns the title of the page with the given `code` added by `commit`.
+///
+/// It is taken from the index in the commit's tree or extracted from the page
+/// if the index doesn't list it.
+fn page_title(repo: &git2::Repository, commit: &git2::Commit, code: &str) -> Result<String> {
+    let tree = commit.tree()?;
+    let indexed_name = Index::from_tree(repo, &tree)?.and_then(|index| {
+        index
+            .entries
+            .into_iter()
+            .find(|entry| entry.code == code)
+            .map(|entry| entry.name)
+    });
+    if let Some(name) = indexed_name {
+        return Ok(name);
+    }
+
+    let page_path = history::page_path(code);
+    let blob = tree
+        .get_path(&page_path)?
+        .to_object(repo)?
+        .into_blob()
+        .map_err(|_| anyhow::anyhow!("{:?} in commit {} is not a file", &page_path, commit.id()))?;
+    let page = String::from_utf8_lossy(blob.content()).into_owned();
+    Ok(crate::page::extract_title(&page))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{index, TestRepo};
+
+    fn signature() -> git2::Signature<'static> {
+        git2::Signature::now("Jane Doe", "jane@example.com").unwrap()
+    }
+
+    fn index_file(codes: &[&str]) -> String {
+        toml::to_string_pretty(&index(codes)).unwrap()
+    }
+
+    fn codes(test: &TestRepo) -> Vec<String> {
+        let head = test.repo.head().unwrap().target().unwrap();
+        let history = history::page_history(&test.repo, head).unwrap();
+        let codes: Vec<String> = history.pages.into_iter().map(|page| page.code).collect();
+        let index = Index::from_tree(
+            &test.repo,
+            &test.repo.find_commit(head).unwrap().tree().unwrap(),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            index
+                .entries
+                .iter()
+                .map(|entry| &entry.code)
+                .collect::<Vec<_>>(),
+            codes.iter().collect::<Vec<_>>()
+        );
+        codes
+    }
+
+    #[test]
+    fn pages_are_saved_after_the_current_pages() {
+        let test = TestRepo::guide(&[("one", "1"), ("two", "2")]);
+        let chain = history::first_parent_chain(&test.repo).unwrap();
+        // the index of the current branch is out of order and the branch lists
+        // its page as the second one
+        let head = test.commit(
+            &[chain[2]],
+            "Reorder index",
+            &[(history::INDEX_PATH, &index_file(&["two", "one"]))],
+        );
+        test.set_head(head);
+        test.repo
+            .checkout_head(Some(git2::build::CheckoutBuilder::new().force()))
+            .unwrap();
+        let three = test.commit(
+            &[chain[1]],
+            &history::page_commit_message("three", "three"),
+            &[
+                ("pages/three.md", "# three\n"),
+                ("workspace/three.rs", "3"),
+                (history::INDEX_PATH, &index_file(&["one", "three"])),
+            ],
+        );
+        test.repo
+            .reference("refs/heads/three", three, false, "test")
+            .unwrap();
+
+        integrate_branch(&test.repo, &signature(), "three").unwrap();
+        assert_eq!(codes(&test), ["one", "two", "three"]);
+        assert_eq!(
+            std::fs::read_to_string(test.path().join("workspace/three.rs")).unwrap(),
+            "3"
+        );
+        assert_eq!(test.changes(), []);
+    }
+
+    #[test]
+    fn conflicts_of_every_page_are_reported() {
+        let test = TestRepo::guide(&[("one", "1"), ("two", "2")]);
+        let chain = history::first_parent_chain(&test.repo).unwrap();
+        let head = test.commit(&[chain[2]], "Add lib", &[("workspace/lib.rs", "lib")]);
+        test.set_head(head);
+
+        let three = test.commit(
+            &[chain[1]],
+            &history::page_commit_message("three", "three"),
+            &[
+                ("pages/three.md", "# three\n"),
+                ("workspace/main.rs", "3"),
+                (history::INDEX_PATH, &index_file(&["one", "three"])),
+            ],
+        );
+        let four = test.commit(
+            &[three],
+            &history::page_commit_message("four", "four"),
+            &[
+                ("pages/four.md", "# four\n"),
+                ("workspace/lib.rs", "4"),
+                (history::INDEX_PATH, &index_file(&["one", "three", "four"])),
+            ],
+        );
+        test.repo
+            .reference("refs/heads/pages", four, false, "test")
+            .unwrap();
+
+        let error = integrate_branch(&test.repo, &signature(), "pages").unwrap_err();
+        let error = error.to_string();
+        assert!(error.contains("page `three` conflicts in:\n    workspace/main.rs\n"));
+        assert!(error.contains("page `four` conflicts in:\n    workspace/lib.rs\n"));
+        assert_eq!(test.repo.head().unwrap().target(), Some(head));
+        assert_eq!(codes(&test), ["one", "two"]);
+    }
+}
@@ -1,4 +1,12 @@
 pub mod build;
+pub mod deploy;
+pub mod import;
 pub mod init;
+pub mod integrate;
+pub mod new;
 pub mod preview;
+pub mod remove;
 pub mod save;
+pub mod serve;
+pub mod split;
+pub mod theme;
@@ -0,0 +1,100 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::context::Index;
+use crate::history;
+
+/// Template used when the project doesn't provide one.
+pub const DEFAULT_PAGE_TEMPLATE: &str = "# {{ title }}\n";
+
+#[derive(Parser)]
+pub struct Opts {
+    /// Title of the new page.
+    title: String,
+}
+
+/// Context passed to the page template.
+#[derive(Serialize)]
+struct NewPageContext<'a> {
+    title: &'a str,
+    code: &'a str,
+}
+
+pub fn execute(opts: &Opts) -> Result<()> {
+    let project = crate::paths::project()
+        .context("current directory is not part of a Codasai project")?
+        .canonicalize()
+        .context("failed to canonicalize project directory")?;
+
+    if let Some(unsaved_page) =
+        crate::page::find_unsaved_page(&project).context("failed to find new page")?
+    {
+        anyhow::bail!(
+            "{:?} is not saved yet, save it before creating a new page",
+            unsaved_page
+        );
+    }
+
+    let config = Config::from_project(&project)?;
+    let index = Index::from_project(&project)?;
+    let code = unique_code(&project, &index, &crate::page::slugify(&opts.title));
+
+    let template_path = project.join(".codasai").join(
+        config
+            .new
+            .template
+            .as_deref()
+            .unwrap_or_else(|| Path::new("page-template.md")),
+    );
+    let template = if template_path.exists() {
+        std::fs::read_to_string(&template_path)
+            .with_context(|| format!("failed to read page template {:?}", &template_path))?
+    } else if config.new.template.is_some() {
+        anyhow::bail!("page template {:?} doesn't exist", &template_path);
+    } else {
+        DEFAULT_PAGE_TEMPLATE.to_string()
+    };
+
+    let mut env = minijinja::Environment::new();
+    env.add_template("page-template", &template)?;
+    let mut page = env
+        .get_template("page-template")?
+        .render(NewPageContext {
+            title: &opts.title,
+            code: &code,
+        })
+        .context("failed to render page template")?;
+    // the template engine strips the template's trailing newline
+    if template.ends_with('\n') {
+        page.push('\n');
+    }
+
+    let page_path = project.join(history::page_path(&code));
+    std::fs::write(&page_path, &page)
+        .with_context(|| format!("failed to write to {:?}", &page_path))?;
+
+    log::info!("created page {:?}", &page_path);
+
+    Ok(())
+}
+
+/// Makes `code` unique by appending a number to it if it's already used by a
+/// page in the index or in `pages/`.
+pub fn unique_code(project: &Path, index: &Index, code: &str) -> String {
+    let is_used = |code: &str| {
+        index.entries.iter().any(|entry| entry.code == code)
+            || project.join(history::page_path(code)).exists()
+    };
+
+    let mut unique_code = code.to_string();
+    let mut n = 2;
+    while is_used(&unique_code) {
+        unique_code = format!("{}-{}", code, n);
+        n += 1;
+    }
+    unique_code
+}
@@ -1,15 +1,23 @@
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use anyhow::{Context, Result};
 use clap::Parser;
-use ignore::Walk;
+use ignore::{Walk, WalkBuilder};
 use minijinja::Environment;
 
-use crate::code;
+use crate::code::Highlighter;
+use crate::commands::build;
+use crate::config::Config;
 use crate::context::{
-    Directory, GlobalContext, GuideContext, Index, PageContext, WorkspaceOutlineBuilder,
+    Directory, GlobalContext, GuideContext, Index, IndexEntry, PageContext, WorkspaceOutlineBuilder,
 };
+use crate::export::{AssetManifest, BuildMode};
+use crate::history;
 use crate::page::PagePreprocessor;
+use crate::paths::ProjectPaths;
+use crate::theme::Theme;
 
 #[derive(Parser)]
 pub struct Opts {
@@ -22,34 +30,333 @@ pub struct Opts {
     /// guide.
     #[clap(long)]
     no_run_server: bool,
+    /// Host name or IP address the server listens on.
+    #[clap(long)]
+    host: Option<String>,
+    /// Port the server listens on.
+    ///
+    /// A free port is used if it is taken.
+    #[clap(long)]
+    port: Option<u16>,
+    /// URL path the preview is served at.
+    #[clap(long)]
+    base_url: Option<String>,
+    /// Code of a saved page to preview instead of the unsaved page.
+    ///
+    /// The page is rendered along with the workspace it was saved with.
+    #[clap(long, conflicts_with = "guide")]
+    page: Option<String>,
+    /// Previews the whole guide with the unsaved page at its end.
+    #[clap(long)]
+    guide: bool,
 }
 
 pub fn execute(opts: &Opts) -> Result<()> {
     // TODO: Take `--path` into account
-    let project_paths = crate::paths::ProjectPaths::new()?;
-    let project = project_paths.project();
-    let export_dir = project_paths.export();
-    let preview_dir = export_dir.join("preview");
+    let mut project_paths = crate::paths::ProjectPaths::new()?;
+    let config = Config::from_project(project_paths.project())?;
+    let base_url = server::normalize_base_url(
+        opts.base_url
+            .as_deref()
+            .or(config.preview.base_url.as_deref())
+            .unwrap_or("/"),
+    );
+    let server_options = |start_page: String| server::ServerOptions {
+        host: opts
+            .host
+            .clone()
+            .or_else(|| config.preview.host.clone())
+            .unwrap_or_else(|| server::DEFAULT_HOST.to_string()),
+        port: opts
+            .port
+            .or(config.preview.port)
+            .unwrap_or(server::DEFAULT_PORT),
+        base_url: base_url.clone(),
+        open: (!opts.no_open).then_some(start_page),
+    };
 
-    // clean previous build
-    if preview_dir.exists() {
-        std::fs::remove_dir_all(&preview_dir)
-            .with_context(|| format!("failed to remove directory {:?}", preview_dir))?;
+    let theme = Arc::new(Theme::from_project(project_paths.project())?);
+    let highlighter = Highlighter::from_project(project_paths.project(), &theme)?;
+
+    if opts.page.is_none() && !opts.guide && !opts.no_run_server {
+        let files = PreviewFiles {
+            project_paths,
+            base_url: base_url.clone(),
+            theme,
+            highlighter,
+        };
+        // report errors before serving instead of on the first request
+        files.render_page().context("failed to render page")?;
+        return server::launch_virtual_server(files, &server_options("preview/".to_string()));
     }
 
-    crate::export::export_public_files(&project_paths)?;
-    export_workspace(project).context("failed to render workspace")?;
+    // previews of saved pages are built like the guide, they use their own
+    // directory so that they don't interfere with `codasai build`
+    let export_dir = crate::paths::cache_dir(project_paths.project(), "preview");
+    project_paths.set_export(export_dir);
+
+    let start_page = if let Some(code) = &opts.page {
+        preview_saved_page(&project_paths, &theme, &highlighter, code, &base_url)?;
+        format!("{}/", code)
+    } else if opts.guide {
+        let code = preview_guide(&project_paths, &theme, &highlighter, &base_url)?;
+        format!("{}/", code)
+    } else {
+        preview_unsaved_page(&project_paths, &theme, &highlighter, &base_url)?;
+        "preview/".to_string()
+    };
+
+    if opts.no_run_server {
+        log::info!("preview exported to {:?}", project_paths.export());
+    } else {
+        server::launch_server(project_paths.export(), &server_options(start_page))?;
+    }
+
+    Ok(())
+}
+
+/// Files of the unsaved page's preview.
+///
+/// They are generated from the project's sources every time they are
+/// requested so that the preview is always up to date. The theme and the
+/// highlighter are only loaded once, so changes to `guide.toml` and to the
+/// custom syntaxes need the preview to be restarted.
+struct PreviewFiles {
+    project_paths: ProjectPaths,
+    base_url: String,
+    theme: Arc<Theme>,
+    highlighter: Highlighter,
+}
+
+impl PreviewFiles {
+    /// Renders the unsaved page.
+    fn render_page(&self) -> Result<String> {
+        let project = self.project_paths.project();
+        let page_path_relative = crate::page::find_unsaved_page(project)
+            .context("failed to find new page")?
+            .ok_or(anyhow::anyhow!("there are no unsaved pages"))?;
+        let guide_context = GuideContext {
+            base_url: self.base_url.clone(),
+            index: Index::default(),
+            assets: AssetManifest::default(),
+            mode: BuildMode::Development,
+        };
+        let template_engine =
+            crate::page::read_theme_templates(project, &self.theme, &guide_context)?;
+
+        render_page(
+            project,
+            &self.theme,
+            &page_path_relative,
+            &template_engine,
+            &guide_context,
+            "preview",
+        )
+    }
+}
+
+impl server::VirtualTree for PreviewFiles {
+    fn generate(&self, path: &str) -> Result<Option<Vec<u8>>> {
+        let paths = &self.project_paths;
+        let path = Path::new(path);
+
+        if path == Path::new("preview/index.html") {
+            return Ok(Some(self.render_page()?.into_bytes()));
+        }
+
+        if path == Path::new("404.html") {
+            // the preview is the only page that can be linked to
+            let guide_context = GuideContext {
+                base_url: self.base_url.clone(),
+                index: Index {
+                    entries: vec![IndexEntry {
+                        name: "Preview".to_string(),
+                        code: "preview".to_string(),
+                    }],
+                },
+                assets: AssetManifest::default(),
+                mode: BuildMode::Development,
+            };
+            let html =
+                crate::export::render_not_found_page(paths.project(), &self.theme, &guide_context)?;
+            return Ok(html.map(String::into_bytes));
+        }
+
+        if let Ok(file) = path.strip_prefix("preview/workspace") {
+            // workspace files are exported with an extra `.html` extension
+            let file = file.with_extension("");
+            if !is_workspace_file(paths.workspace(), &file) {
+                return Ok(None);
+            }
+            return Ok(Some(
+                highlight_workspace_file(paths.workspace(), &file, &self.highlighter)?.into_bytes(),
+            ));
+        }
 
-    let template_engine = crate::page::read_theme_templates(project)?;
-    export_unsaved_page(project, &template_engine).context("failed to render page")?;
+        if let Ok(file) = path.strip_prefix("public") {
+            return crate::export::read_public_file(paths.project(), &self.theme, file);
+        }
 
-    if !opts.no_run_server {
-        server::launch_server(export_dir, !opts.no_open);
+        Ok(None)
     }
+}
+
+/// Exports the unsaved page and the current workspace to `preview/` in the
+/// project's export directory.
+fn preview_unsaved_page(
+    project_paths: &ProjectPaths, theme: &Arc<Theme>, highlighter: &Highlighter, base_url: &str,
+) -> Result<()> {
+    let project = project_paths.project();
+    let preview_dir = project_paths.export().join("preview");
+
+    crate::export::export_public_files(project_paths, theme, BuildMode::Development)?;
+    export_workspace(project, &preview_dir.join("workspace"), highlighter)
+        .context("failed to render workspace")?;
+
+    let guide_context = GuideContext {
+        base_url: base_url.to_string(),
+        index: Index::default(),
+        assets: AssetManifest::default(),
+        mode: BuildMode::Development,
+    };
+    let template_engine = crate::page::read_theme_templates(project, theme, &guide_context)?;
+    export_unsaved_page(
+        project,
+        theme,
+        &template_engine,
+        &guide_context,
+        &preview_dir,
+    )
+    .context("failed to render page")?;
 
     Ok(())
 }
 
+/// Exports the saved page with the given `code` to `<code>/` in the project's
+/// export directory.
+///
+/// The page is rendered the same way `codasai build` does.
+fn preview_saved_page(
+    project_paths: &ProjectPaths, theme: &Arc<Theme>, highlighter: &Highlighter, code: &str,
+    base_url: &str,
+) -> Result<()> {
+    let project = project_paths.project();
+    let repo = git2::Repository::open(project)
+        .with_context(|| format!("failed to open repository at {:?}", project))?;
+
+    let chain = history::first_parent_chain(&repo)?;
+    let commit = repo.find_commit(chain[history::find_page_commit(&repo, &chain, code)?])?;
+    let head = repo.find_commit(*chain.last().unwrap())?;
+
+    let index = build::guide_index(project, &repo, &head)?;
+    let page_num = index
+        .entries
+        .iter()
+        .position(|entry| entry.code == code)
+        .ok_or_else(|| anyhow::anyhow!("page `{}` is not in the index", code))?;
+    let guide_ctx = GuideContext {
+        index,
+        base_url: base_url.to_string(),
+        assets: AssetManifest::default(),
+        mode: BuildMode::Development,
+    };
+
+    crate::export::export_public_files(project_paths, theme, BuildMode::Development)?;
+    crate::export::export_not_found_page(project_paths, theme, &guide_ctx)?;
+    build::export_saved_page(
+        project_paths,
+        &repo,
+        &commit,
+        &guide_ctx,
+        theme,
+        highlighter,
+        page_num,
+    )
+    .with_context(|| format!("failed to render page `{}`", code))
+}
+
+/// Exports the saved pages followed by the unsaved page to the project's
+/// export directory.
+///
+/// Returns the code of the unsaved page.
+fn preview_guide(
+    project_paths: &ProjectPaths, theme: &Arc<Theme>, highlighter: &Highlighter, base_url: &str,
+) -> Result<String> {
+    let project = project_paths.project();
+    let page_path_relative = crate::page::find_unsaved_page(project)
+        .context("failed to find new page")?
+        .ok_or(anyhow::anyhow!("there are no unsaved pages"))?;
+    let code = page_path_relative
+        .file_stem()
+        .unwrap()
+        .to_string_lossy()
+        .into_owned();
+
+    let repo = git2::Repository::open(project)
+        .with_context(|| format!("failed to open repository at {:?}", project))?;
+    let head = match repo.head() {
+        Ok(head) => Some(
+            head.peel_to_commit()
+                .context("repository head is not a commit")?,
+        ),
+        Err(e) if e.code() == git2::ErrorCode::UnbornBranch => None,
+        Err(e) => return Err(e).context("failed to get repository head"),
+    };
+
+    let mut index = match &head {
+        Some(head) => build::guide_index(project, &repo, head)?,
+        None => Index::from_project(project)?,
+    };
+    let saved_pages = index.entries.len();
+    let page = std::fs::read_to_string(project.join(&page_path_relative))
+        .with_context(|| format!("failed to read {:?}", &page_path_relative))?;
+    index.entries.push(IndexEntry {
+        name: crate::page::extract_title(&page),
+        code: code.clone(),
+    });
+    let guide_ctx = GuideContext {
+        index,
+        base_url: base_url.to_string(),
+        assets: AssetManifest::default(),
+        mode: BuildMode::Development,
+    };
+
+    crate::export::export_public_files(project_paths, theme, BuildMode::Development)?;
+    crate::export::export_not_found_page(project_paths, theme, &guide_ctx)?;
+    let exported = match &head {
+        Some(head) => {
+            build::export_history(project_paths, &repo, head, &guide_ctx, theme, highlighter)?
+        },
+        None => 0,
+    };
+    anyhow::ensure!(
+        exported == saved_pages,
+        "the history has {} pages but the index lists {}, run `codasai build` for details",
+        exported,
+        saved_pages
+    );
+
+    let out_dir = project_paths.export().join(&code);
+    export_workspace(project, &out_dir.join("workspace"), highlighter)
+        .context("failed to render workspace")?;
+
+    let template_engine = crate::page::read_theme_templates(project, theme, &guide_ctx)?;
+    let reader_html = render_page(
+        project,
+        theme,
+        &page_path_relative,
+        &template_engine,
+        &guide_ctx,
+        &code,
+    )
+    .context("failed to render page")?;
+    let reader_path = out_dir.join("index.html");
+    std::fs::write(&reader_path, &reader_html)
+        .with_context(|| format!("failed to write to {:?}", &reader_path))?;
+
+    Ok(code)
+}
+
 /// Traverses the project's workspace and builds an outline
 ///
 /// It respects ignore files.
@@ -57,7 +364,6 @@ fn build_workspace_outline(project: &Path) -> Result<Directory> {
     let workspace = project.join("workspace");
 
     let walker = Walk::new(&workspace)
-        .into_iter()
         .filter_map(|entry| {
             if let Err(e) = &entry {
                 log::warn!("failed to read entry {:?}", e);
@@ -85,31 +391,48 @@ fn build_workspace_outline(project: &Path) -> Result<Directory> {
     Ok(ws_builder.finish())
 }
 
-/// Exports the whole workspace in the project
+/// Returns whether `file`, relative to `workspace`, is one of the workspace
+/// files shown in the preview.
+///
+/// Like the exported workspace, it respects ignore files so that files like
+/// secrets or build artifacts are never served.
+fn is_workspace_file(workspace: &Path, file: &Path) -> bool {
+    let path = workspace.join(file);
+    let ancestors = path.clone();
+    // only the ancestors of `file` are walked
+    WalkBuilder::new(workspace)
+        .filter_entry(move |entry| ancestors.starts_with(entry.path()))
+        .build()
+        .filter_map(Result::ok)
+        .any(|entry| entry.path() == path && entry.file_type().is_some_and(|ft| ft.is_file()))
+}
+
+/// Exports the whole workspace in the project to `preview_ws`, highlighted by
+/// `highlighter`.
 ///
 /// It respects ignore files.
-fn export_workspace(project: &Path) -> Result<()> {
+pub fn export_workspace(
+    project: &Path, preview_ws: &Path, highlighter: &Highlighter,
+) -> Result<()> {
     let workspace = project.join("workspace");
 
-    let walker = Walk::new(&workspace).into_iter().filter_map(|entry| {
+    let walker = Walk::new(&workspace).filter_map(|entry| {
         if let Err(e) = &entry {
             log::warn!("failed to read entry {:?}", e);
         }
         entry.ok()
     });
 
-    let preview_ws = project.join(".codasai/export/preview/workspace");
     if preview_ws.exists() {
-        std::fs::remove_dir_all(&preview_ws)
-            .with_context(|| format!("failed to remove directory {:?}", &preview_ws))?;
+        std::fs::remove_dir_all(preview_ws)
+            .with_context(|| format!("failed to remove directory {:?}", preview_ws))?;
     }
-    std::fs::create_dir_all(&preview_ws)
-        .with_context(|| format!("failed to create dir {:?}", &preview_ws))?;
-
+    std::fs::create_dir_all(preview_ws)
+        .with_context(|| format!("failed to create dir {:?}", preview_ws))?;
     for entry in walker {
         if let Ok(metadata) = entry.metadata() {
             if metadata.is_file() {
-                export_workspace_file(entry.path(), project, &preview_ws)
+                export_workspace_file(entry.path(), project, preview_ws, highlighter)
                     .with_context(|| format!("failed to render file {:?}", entry.path()))?;
             }
         }
@@ -123,9 +446,11 @@ fn export_workspace(project: &Path) -> Result<()> {
 ///
 /// It highlights the exported files it they're supported by the highlighting
 /// engine.
-fn export_workspace_file(file: &Path, project: &Path, preview_ws: &Path) -> Result<()> {
+fn export_workspace_file(
+    file: &Path, project: &Path, preview_ws: &Path, highlighter: &Highlighter,
+) -> Result<()> {
     let relative_path = file
-        .strip_prefix(&project.join("workspace"))
+        .strip_prefix(project.join("workspace"))
         .expect("failed to strip prefix");
     let mut preview_path = preview_ws.join(relative_path);
 
@@ -144,72 +469,136 @@ fn export_workspace_file(file: &Path, project: &Path, preview_ws: &Path) -> Resu
     std::fs::create_dir_all(parent)
         .with_context(|| format!("failed to create directory {:?}", parent))?;
 
-    let contents_unsafe = match std::fs::read(file) {
+    let contents =
+        highlight_workspace_file(&project.join("workspace"), relative_path, highlighter)?;
+    std::fs::write(&preview_path, &contents)
+        .with_context(|| format!("failed to write to {:?}", &preview_path))?;
+
+    Ok(())
+}
+
+/// Reads the file at `relative_path` in `workspace` and renders it to html.
+///
+/// It highlights the file if it's supported by the highlighting engine.
+fn highlight_workspace_file(
+    workspace: &Path, relative_path: &Path, highlighter: &Highlighter,
+) -> Result<String> {
+    let file = workspace.join(relative_path);
+    let contents_unsafe = match std::fs::read(&file) {
         Ok(c) => String::from_utf8(c).unwrap_or_else(|_| "BINARY FILE".to_string()),
         Err(e) => return Err(e).with_context(|| format!("failed to read file {:?}", file))
     };
 
     // Only languages supported by `syntect` are highlighted.
-    // Files that don't have a supported file extension are only escaped.
-    let extension = file.extension().unwrap_or_default().to_str().unwrap();
-    let contents = code::escape_and_highlight(&contents_unsafe, extension);
-    std::fs::write(&preview_path, &contents)
-        .with_context(|| format!("failed to write to {:?}", &preview_path))?;
-
-    Ok(())
+    // Files without a supported syntax are only escaped.
+    Ok(highlighter.escape_and_highlight(&contents_unsafe, relative_path))
 }
 
 /// Exports the unsaved page in the project.
 ///
-/// It uses `template.html` in `template_engine` to render the page.
-pub fn export_unsaved_page(project: &Path, template_engine: &Environment) -> Result<()> {
+/// It uses `template.html` in `template_engine`, read from `theme`, to render
+/// the page to `index.html` in `preview` as the only page of `guide_context`.
+pub fn export_unsaved_page(
+    project: &Path, theme: &Arc<Theme>, template_engine: &Environment,
+    guide_context: &GuideContext, preview: &Path,
+) -> Result<()> {
     let page_path_relative = crate::page::find_unsaved_page(project)
         .context("failed to find new page")?
         .ok_or(anyhow::anyhow!("there are no unsaved pages"))?;
+
+    let reader_html = render_page(
+        project,
+        theme,
+        &page_path_relative,
+        template_engine,
+        guide_context,
+        "preview",
+    )?;
+
+    std::fs::create_dir_all(preview)
+        .with_context(|| format!("failed to create directory {:?}", preview))?;
+
+    let reader_path = preview.join("index.html");
+    std::fs::write(&reader_path, &reader_html)
+        .with_context(|| format!("failed to write to {:?}", &reader_path))?;
+
+    Ok(())
+}
+
+/// Renders the page at `page_path_relative` along with the project's current
+/// workspace.
+///
+/// It uses `template.html` in `template_engine`, read from `theme`, to render
+/// the page. The page is exported as `code` and, if `guide_context`'s index
+/// lists it, it is linked to its neighbours.
+pub fn render_page(
+    project: &Path, theme: &Arc<Theme>, page_path_relative: &Path, template_engine: &Environment,
+    guide_context: &GuideContext, code: &str,
+) -> Result<String> {
     // `page` as given by git2 is relative to the git repository root but we need
     // the absolute path.
     let page_path = project.join(page_path_relative);
     let page = std::fs::read_to_string(&page_path)
         .with_context(|| format!("failed to read {:?}", &page_path))?;
 
-    let guide_context = GuideContext {
-        base_url: "/".to_string(),
-        index: Index::default(),
-    };
-
-    let preprocessor = PagePreprocessor::new(&guide_context);
+    let preprocessor = PagePreprocessor::new(project, theme, guide_context);
 
     let page_path_str = page_path.display().to_string();
     let page_html = crate::page::markdown_to_html(&preprocessor.preprocess(&page_path_str, &page)?);
 
+    let entries = &guide_context.index.entries;
+    let number = entries.iter().position(|entry| entry.code == code);
     let title = crate::page::extract_title(&page);
     let page_context = PageContext {
-        number: 0,
+        number: number.unwrap_or(0),
         title,
         content: page_html,
-        code: "preview".to_string(),
+        date: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs() as i64),
+        code: code.to_string(),
         workspace: build_workspace_outline(project)?,
-        previous_page_code: None,
-        next_page_code: None,
+        previous_page_code: number
+            .and_then(|number| entries.get(number.wrapping_sub(1)))
+            .map(|entry| entry.code.clone()),
+        next_page_code: number
+            .and_then(|number| entries.get(number + 1))
+            .map(|entry| entry.code.clone()),
     };
 
     let context = GlobalContext {
         page: &page_context,
-        guide: &guide_context,
+        guide: guide_context,
     };
 
-    let reader_html = template_engine
+    template_engine
         .get_template("template.html")?
         .render(&context)
-        .context("failed to render template")?;
-
-    let preview = project.join(".codasai/export/preview");
-    std::fs::create_dir_all(&preview)
-        .with_context(|| format!("failed to create directory {:?}", preview))?;
-
-    let reader_path = preview.join("index.html");
-    std::fs::write(&reader_path, &reader_html)
-        .with_context(|| format!("failed to write to {:?}", &reader_path))?;
+        .context("failed to render template")
+}
 
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestRepo;
+
+    #[test]
+    fn ignored_workspace_files_are_not_served() {
+        let test = TestRepo::new();
+        let workspace = test.path().join("workspace");
+        std::fs::create_dir_all(workspace.join("src")).unwrap();
+        std::fs::create_dir_all(workspace.join("target")).unwrap();
+        std::fs::write(workspace.join(".gitignore"), ".env\ntarget/\n").unwrap();
+        std::fs::write(workspace.join(".env"), "SECRET=1").unwrap();
+        std::fs::write(workspace.join("target/app"), "").unwrap();
+        std::fs::write(workspace.join("src/main.rs"), "fn main() {}").unwrap();
+        std::fs::write(test.path().join("secret.txt"), "").unwrap();
+
+        assert!(is_workspace_file(&workspace, Path::new("src/main.rs")));
+        assert!(!is_workspace_file(&workspace, Path::new("src")));
+        assert!(!is_workspace_file(&workspace, Path::new(".env")));
+        assert!(!is_workspace_file(&workspace, Path::new("target/app")));
+        assert!(!is_workspace_file(&workspace, Path::new("../secret.txt")));
+        assert!(!is_workspace_file(&workspace, Path::new("missing.rs")));
+    }
 }
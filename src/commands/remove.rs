@@ -0,0 +1,124 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use clap::Parser;
+
+use crate::context::Index;
+use crate::history;
+
+#[derive(Parser)]
+pub struct Opts {
+    /// Code of the page to remove.
+    code: String,
+}
+
+pub fn execute(opts: &Opts) -> Result<()> {
+    let project = crate::paths::project()
+        .context("current directory is not part of a Codasai project")?
+        .canonicalize()
+        .context("failed to canonicalize project directory")?;
+
+    let repo = git2::Repository::open(&project)
+        .with_context(|| format!("failed to open repository at {:?}", &project))?;
+
+    remove_page(&project, &repo, &opts.code)?;
+    log::info!("removed page `{}`", &opts.code);
+
+    Ok(())
+}
+
+/// Removes the page with the given `code` from the history of `repo` and from
+/// the project at `project`.
+fn remove_page(project: &Path, repo: &git2::Repository, code: &str) -> Result<()> {
+    let chain = history::first_parent_chain(repo)?;
+    let position = history::find_page_commit(repo, &chain, code)?;
+    let page_path = history::page_path(code);
+
+    // Dropping the page commit is enough to fold its workspace changes into the
+    // following commit because every commit's tree holds the whole workspace.
+    let parent = position.checked_sub(1).map(|i| chain[i]);
+    let new_head = history::replay(repo, parent, &chain[position + 1..], |tree| {
+        let tree = history::tree_with_entry(repo, Some(tree), &page_path, None)?;
+        history::tree_with_index(repo, &repo.find_tree(tree)?, |index| {
+            index.entries.retain(|entry| entry.code != code)
+        })
+    })?
+    .ok_or_else(|| anyhow::anyhow!("cannot remove the only commit in the repository"))?;
+
+    history::update_head(repo, new_head, &format!("codasai: remove page {}", code))?;
+
+    let mut index = Index::from_project(project)?;
+    index.entries.retain(|entry| entry.code != code);
+    index.write_to_project(project)?;
+
+    let page_file = project.join(&page_path);
+    if page_file.exists() {
+        std::fs::remove_file(&page_file)
+            .with_context(|| format!("failed to remove {:?}", &page_file))?;
+    }
+
+    history::stage_paths(repo, &[&page_path])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestRepo;
+
+    fn codes(test: &TestRepo, head: git2::Oid) -> Vec<String> {
+        let history = history::page_history(&test.repo, head).unwrap();
+        history.pages.into_iter().map(|page| page.code).collect()
+    }
+
+    #[test]
+    fn removes_a_page_in_the_middle() {
+        let test = TestRepo::guide(&[("one", "1"), ("two", "2"), ("three", "3")]);
+        let old_chain = history::first_parent_chain(&test.repo).unwrap();
+
+        remove_page(test.path(), &test.repo, "two").unwrap();
+
+        let chain = history::first_parent_chain(&test.repo).unwrap();
+        assert_eq!(chain[..2], old_chain[..2]);
+        assert_eq!(chain.len(), 3);
+        let head = chain[2];
+        assert_eq!(codes(&test, head), ["one", "three"]);
+        assert_eq!(test.file(head, "pages/two.md"), None);
+        assert_eq!(test.file(head, "workspace/main.rs").unwrap(), "3");
+        let index = Index::from_tree(
+            &test.repo,
+            &test.repo.find_commit(head).unwrap().tree().unwrap(),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(index.entries.len(), 2);
+
+        assert!(!test.path().join("pages/two.md").exists());
+        assert_eq!(Index::from_project(test.path()).unwrap().entries.len(), 2);
+        assert!(test.changes().is_empty());
+    }
+
+    #[test]
+    fn removes_the_last_page() {
+        let test = TestRepo::guide(&[("one", "1"), ("two", "2"), ("three", "3")]);
+        let old_chain = history::first_parent_chain(&test.repo).unwrap();
+
+        remove_page(test.path(), &test.repo, "three").unwrap();
+
+        let chain = history::first_parent_chain(&test.repo).unwrap();
+        assert_eq!(chain, old_chain[..3]);
+        assert_eq!(codes(&test, chain[2]), ["one", "two"]);
+        assert!(!test.path().join("pages/three.md").exists());
+        assert_eq!(Index::from_project(test.path()).unwrap().entries.len(), 2);
+        // the workspace changes of the last page are left unsaved
+        assert_eq!(
+            test.changes(),
+            [("workspace/main.rs".to_string(), git2::Status::WT_MODIFIED)]
+        );
+    }
+
+    #[test]
+    fn missing_pages_are_an_error() {
+        let test = TestRepo::guide(&[("one", "1")]);
+        assert!(remove_page(test.path(), &test.repo, "two").is_err());
+    }
+}
@@ -1,24 +1,58 @@
+use std::path::Path;
 use std::process::Command;
+use std::sync::Arc;
 
 use anyhow::{Context, Result};
 use clap::Parser;
 
-use crate::context::{Index, IndexEntry};
+use crate::code::Highlighter;
+use crate::config::{Config, SaveConfig};
+use crate::context::{GuideContext, Index, IndexEntry};
+use crate::export::{AssetManifest, BuildMode};
+use crate::history;
+use crate::paths::ProjectPaths;
+use crate::theme::Theme;
+
+/// Paths, relative to the project, that are included in page commits.
+const SAVED_PATHS: &[&str] = &["pages", "workspace", history::INDEX_PATH];
+
+/// Paths, relative to the project, whose changes are folded into the most
+/// recently saved page by `--amend`.
+const AMENDED_PATHS: &[&str] = &["pages", "workspace", "static", history::INDEX_PATH];
 
 #[derive(Parser)]
-pub struct Opts {}
+pub struct Opts {
+    /// Folds the current changes into the most recently saved page instead of
+    /// saving a new one.
+    #[clap(long)]
+    amend: bool,
+    /// Saves the page even if it doesn't pass the pre-save checks.
+    #[clap(long)]
+    force: bool,
+}
 
-pub fn execute(_opts: &Opts) -> Result<()> {
+pub fn execute(opts: &Opts) -> Result<()> {
     let project = crate::paths::project()
         .context("current directory is not part of a Codasai project")?
         .canonicalize()
         .context("failed to canonicalize project directory")?;
 
+    let config = Config::from_project(&project)?;
+    let repo = git2::Repository::open(&project)
+        .with_context(|| format!("failed to open repository at {:?}", &project))?;
+
+    if opts.amend {
+        return amend_last_page(&project, &repo, &config.save, opts.force);
+    }
+
     let mut index = Index::from_project(&project)?;
+    let previous_index = index.clone();
 
     let new_page_path =
         crate::page::find_unsaved_page(&project).context("failed to find new page")?;
     let new_page_path = new_page_path.ok_or(anyhow::anyhow!("there are no unsaved pages"))?;
+    check_page(&project, &new_page_path, &config.save, opts.force)?;
+    let new_page_path = project.join(new_page_path);
     let new_page_content = std::fs::read_to_string(&new_page_path)
         .with_context(|| format!("failed to read new page at {:?}", &new_page_path))?;
     let new_page_title = crate::page::extract_title(&new_page_content);
@@ -38,44 +72,330 @@ pub fn execute(_opts: &Opts) -> Result<()> {
 
     index.write_to_project(&project)?;
 
-    commit_page(new_page_title, new_page_file_name)?;
+    let message = history::page_commit_message(&new_page_title, &new_page_file_name);
+    if let Err(e) = commit_page(&repo, &config.save, &message) {
+        // don't leave an entry for a page that wasn't saved
+        previous_index.write_to_project(&project)?;
+        return Err(e).context("failed to commit page");
+    }
 
     Ok(())
 }
 
-/// Commits the current page including the workspace.
+/// Folds the current changes into the most recently saved page.
 ///
-/// It sets the committer as Codasai CLI.
-fn commit_page(new_page_title: String, new_page_file_name: String) -> Result<(), anyhow::Error> {
-    let git_add_output = Command::new("git")
-        .args(&["add", "pages/", "workspace/"])
-        .output()
-        .context("failed to invoke \"git add -A\"")?;
-    if !git_add_output.status.success() {
-        let git_error = String::from_utf8_lossy(&git_add_output.stderr);
-        anyhow::bail!("`git add` exited with error:\n\n{}", git_error);
-    }
-    let git_commit_message = format!(
-        "Add page: {}\nCode: {}",
-        &new_page_title, &new_page_file_name
+/// The page's title is extracted again and its index entry is updated.
+fn amend_last_page(
+    project: &Path, repo: &git2::Repository, config: &SaveConfig, force: bool,
+) -> Result<()> {
+    anyhow::ensure!(
+        crate::page::find_unsaved_page(project)
+            .context("failed to find new page")?
+            .is_none(),
+        "there is an unsaved page, save it instead of amending the last one"
     );
-    let git_commit_output = Command::new("git")
-        .args(&[
-            "-c",
-            "committer.name=Codasai CLI",
-            "-c",
-            "committer.email=codasai.cli@gmail.com",
-            "commit",
-            "-m",
-            &git_commit_message,
-        ])
-        .output()
-        .context("failed to invoke \"git add -A\"")?;
-
-    if !git_commit_output.status.success() {
-        let git_error = String::from_utf8_lossy(&git_add_output.stderr);
-        anyhow::bail!("`git commit` exited with error:\n\n{}", git_error);
+
+    let head = repo
+        .head()
+        .context("failed to get repository head")?
+        .peel_to_commit()
+        .context("repository head is not a commit")?;
+    let code = history::page_changes(repo, &head)?
+        .added
+        .pop()
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+            "the last commit doesn't save a page, only the most recently saved page can be amended"
+        )
+        })?;
+
+    check_page(project, &history::page_path(&code), config, force)?;
+
+    let page_path = project.join(history::page_path(&code));
+    let page = std::fs::read_to_string(&page_path)
+        .with_context(|| format!("failed to read page at {:?}", &page_path))?;
+    let title = crate::page::extract_title(&page);
+
+    let mut index = Index::from_project(project)?;
+    let previous_index = index.clone();
+    let entry = index
+        .entries
+        .iter_mut()
+        .find(|entry| entry.code == code)
+        .ok_or_else(|| anyhow::anyhow!("page `{}` is not in the index", &code))?;
+    entry.name = title.clone();
+    index.write_to_project(project)?;
+
+    let amend = || -> Result<git2::Oid> {
+        let tree = saved_tree(repo, Some(&head.tree()?), AMENDED_PATHS)?;
+        let message = history::page_commit_message(&title, &code);
+        Ok(head.amend(
+            Some("HEAD"),
+            None,
+            Some(&config.committer_signature()?),
+            None,
+            Some(&message),
+            Some(&tree),
+        )?)
     };
+    if let Err(e) = amend() {
+        previous_index.write_to_project(project)?;
+        return Err(e).context("failed to amend page");
+    }
+
+    log::info!("amended page `{}`", &code);
+
+    Ok(())
+}
+
+/// Runs the pre-save checks on the page at `page_path_relative`.
+///
+/// The page must render the same way it does in `codasai preview`, the
+/// public files, like the theme's Sass files and syntax themes, and the
+/// workspace must export to a temporary directory and the check command in
+/// the config, if any, must succeed. Diagnostics are printed for every failed
+/// check and, unless `force` is set, an error is returned.
+fn check_page(
+    project: &Path, page_path_relative: &Path, config: &SaveConfig, force: bool,
+) -> Result<()> {
+    let mut failed = !check_export(project, page_path_relative)?;
+
+    if let Some(check) = &config.check {
+        let workspace = project.join("workspace");
+        log::info!("running check command `{}`", check);
+        let output = shell_command(check)
+            .current_dir(&workspace)
+            .output()
+            .with_context(|| format!("failed to invoke check command `{}`", check))?;
+        if !output.status.success() {
+            log::error!(
+                "check command `{}` exited with {}:\n\n{}{}",
+                check,
+                output.status,
+                String::from_utf8_lossy(&output.stdout),
+                String::from_utf8_lossy(&output.stderr)
+            );
+            failed = true;
+        }
+    }
+
+    if failed {
+        anyhow::ensure!(
+            force,
+            "page didn't pass the pre-save checks, use `--force` to save it anyway"
+        );
+        log::warn!("saving page even though it didn't pass the pre-save checks");
+    }
 
     Ok(())
 }
+
+/// Renders the page at `page_path_relative` and exports the public files and
+/// the workspace of the project to a temporary directory, which is removed
+/// afterwards.
+///
+/// Returns whether everything was exported, the errors are printed.
+fn check_export(project: &Path, page_path_relative: &Path) -> Result<bool> {
+    let theme = match Theme::from_project(project) {
+        Ok(theme) => Arc::new(theme),
+        Err(e) => {
+            log::error!("theme failed to load: {:?}", e);
+            return Ok(false);
+        },
+    };
+    let mut passed = true;
+
+    let guide_context = GuideContext {
+        base_url: "/".to_string(),
+        index: Index::default(),
+        assets: AssetManifest::default(),
+        mode: BuildMode::Development,
+    };
+    let rendered = crate::page::read_theme_templates(project, &theme, &guide_context)
+        .context("failed to read templates")
+        .and_then(|templates| {
+            crate::commands::preview::render_page(
+                project,
+                &theme,
+                page_path_relative,
+                &templates,
+                &guide_context,
+                "preview",
+            )
+        });
+    if let Err(e) = rendered {
+        log::error!("page {:?} failed to render: {:?}", page_path_relative, e);
+        passed = false;
+    }
+
+    let export_dir = tempfile::tempdir().context("failed to create temporary directory")?;
+    let mut project_paths = ProjectPaths::from_project(project.to_path_buf());
+    project_paths.set_export(export_dir.path().to_path_buf());
+    let exported = crate::export::export_public_files(&project_paths, &theme, guide_context.mode);
+    if let Err(e) = exported {
+        log::error!("public files failed to export: {:?}", e);
+        passed = false;
+    }
+
+    let exported = Highlighter::from_project(project, &theme).and_then(|highlighter| {
+        crate::commands::preview::export_workspace(
+            project,
+            &project_paths.export().join("workspace"),
+            &highlighter,
+        )
+    });
+    if let Err(e) = exported {
+        log::error!("workspace failed to export: {:?}", e);
+        passed = false;
+    }
+
+    Ok(passed)
+}
+
+/// Creates a command that runs `command` through the platform's shell.
+fn shell_command(command: &str) -> Command {
+    if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.args(["/C", command]);
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.args(["-c", command]);
+        shell
+    }
+}
+
+/// Commits the current page including the workspace and the index.
+fn commit_page(repo: &git2::Repository, config: &SaveConfig, message: &str) -> Result<git2::Oid> {
+    let parent = match repo.head() {
+        Ok(head) => Some(
+            head.peel_to_commit()
+                .context("repository head is not a commit")?,
+        ),
+        Err(e) if e.code() == git2::ErrorCode::UnbornBranch => None,
+        Err(e) => return Err(e).context("failed to get repository head"),
+    };
+
+    let base = parent.as_ref().map(|parent| parent.tree()).transpose()?;
+    let tree = saved_tree(repo, base.as_ref(), SAVED_PATHS)?;
+
+    let commit = repo.commit(
+        Some("HEAD"),
+        &config.author_signature(repo)?,
+        &config.committer_signature()?,
+        message,
+        &tree,
+        &parent.iter().collect::<Vec<_>>(),
+    )?;
+
+    Ok(commit)
+}
+
+/// Stages `paths` and builds the tree of a page commit.
+///
+/// Only `paths` are taken from the working directory, anything else that is
+/// staged is left staged and the rest of the tree is taken from `base`.
+fn saved_tree<'r>(
+    repo: &'r git2::Repository, base: Option<&git2::Tree>, paths: &[&str],
+) -> Result<git2::Tree<'r>> {
+    let mut git_index = repo.index().context("failed to read git index")?;
+    git_index
+        .add_all(paths, git2::IndexAddOption::DEFAULT, None)
+        .context("failed to stage page files")?;
+    // `add_all` doesn't stage deleted files
+    git_index
+        .update_all(paths, None)
+        .context("failed to stage page files")?;
+    git_index.write().context("failed to write git index")?;
+    let index_tree = repo.find_tree(git_index.write_tree()?)?;
+
+    let mut tree = base.map(|base| base.id());
+    for path in paths {
+        let base = tree.map(|tree| repo.find_tree(tree)).transpose()?;
+        tree = Some(history::tree_with_entry_from(
+            repo,
+            base.as_ref(),
+            Path::new(path),
+            &index_tree,
+        )?);
+    }
+
+    Ok(repo.find_tree(tree.unwrap())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{write_files, TestRepo};
+
+    #[test]
+    fn pages_that_export_pass_the_checks() {
+        let project = TestRepo::project();
+        write_files(
+            project.path(),
+            &[
+                ("pages/one.md", "# One"),
+                ("workspace/main.rs", "fn main() {}"),
+            ],
+        );
+
+        check_page(
+            project.path(),
+            Path::new("pages/one.md"),
+            &SaveConfig::default(),
+            false,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn theme_errors_fail_the_checks() {
+        let project = TestRepo::project();
+        write_files(project.path(), &[("pages/one.md", "# One")]);
+        let config_path = project.path().join(".codasai/guide.toml");
+        let mut config = std::fs::read_to_string(&config_path).unwrap();
+        config.push_str("\n[theme.options]\nmissing = true\n");
+        std::fs::write(&config_path, config).unwrap();
+
+        let check = |force| {
+            check_page(
+                project.path(),
+                Path::new("pages/one.md"),
+                &SaveConfig::default(),
+                force,
+            )
+        };
+        let error = check(false).unwrap_err();
+        assert!(error.to_string().contains("pre-save checks"));
+        check(true).unwrap();
+    }
+
+    #[test]
+    fn static_files_are_only_saved_when_amending() {
+        let test = TestRepo::guide(&[("one", "1")]);
+        let config = SaveConfig {
+            author: Some(crate::config::Identity::codasai()),
+            ..SaveConfig::default()
+        };
+        let index = toml::to_string_pretty(&crate::testing::index(&["one", "two"])).unwrap();
+        write_files(
+            test.path(),
+            &[
+                ("pages/two.md", "# Two"),
+                ("static/logo.svg", "<svg></svg>"),
+                (history::INDEX_PATH, &index),
+            ],
+        );
+
+        let saved = commit_page(&test.repo, &config, "Add two").unwrap();
+        assert!(test.file(saved, "pages/two.md").is_some());
+        assert_eq!(test.file(saved, "static/logo.svg"), None);
+
+        amend_last_page(test.path(), &test.repo, &config, false).unwrap();
+        let amended = test.repo.head().unwrap().target().unwrap();
+        assert_eq!(
+            test.file(amended, "static/logo.svg").as_deref(),
+            Some("<svg></svg>")
+        );
+    }
+}
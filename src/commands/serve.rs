@@ -0,0 +1,90 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use clap::Parser;
+
+use crate::commands::build;
+use crate::config::Config;
+use crate::export::BuildMode;
+use crate::paths;
+
+#[derive(Parser)]
+pub struct Opts {
+    /// Indicates under what url the guide is served. Useful to test guides
+    /// built for sites like Github/Gitlab pages
+    ///
+    /// By default, it is the server's root.
+    #[clap(long, default_value = "/")]
+    base_url: String,
+
+    /// Directory where the built guide will be stored.
+    ///
+    /// By default, it is `.codasai/cache/serve/`.
+    #[clap(long)]
+    export_dir: Option<PathBuf>,
+
+    /// Git reference (branch, tag or commit) the guide is built from.
+    ///
+    /// By default, the guide is built from `HEAD` or, if the guide has
+    /// variants, every variant is built from its own reference.
+    #[clap(long = "ref")]
+    reference: Option<String>,
+
+    /// Builds the guide like `codasai build --production` does.
+    #[clap(long)]
+    production: bool,
+
+    /// Host name or IP address the server listens on.
+    #[clap(long)]
+    host: Option<String>,
+
+    /// Port the server listens on.
+    ///
+    /// A free port is used if it is taken.
+    #[clap(long)]
+    port: Option<u16>,
+
+    /// Indicates if the browser should not be open.
+    #[clap(long)]
+    no_open: bool,
+}
+
+pub fn execute(opts: &Opts) -> Result<()> {
+    let mut project_paths = paths::ProjectPaths::new()?;
+    // the server runs until the process is interrupted, so the guide is built
+    // to a directory that the next run replaces rather than a temporary one
+    let export_dir = match opts.export_dir.clone() {
+        Some(export_dir) => export_dir,
+        None => paths::cache_dir(project_paths.project(), "serve"),
+    };
+    project_paths.set_export(export_dir);
+
+    let config = Config::from_project(project_paths.project())?;
+    let base_url = server::normalize_base_url(&opts.base_url);
+    let mode = if opts.production {
+        BuildMode::Production
+    } else {
+        BuildMode::Development
+    };
+    build::build(&project_paths, opts.reference.as_deref(), &base_url, mode)?;
+
+    // single guides don't have a landing page so the first page is opened
+    let start_page = build::start_page(&project_paths, opts.reference.as_deref())?
+        .map(|code| format!("{}/", code))
+        .unwrap_or_default();
+
+    let options = server::ServerOptions {
+        host: opts
+            .host
+            .clone()
+            .or(config.preview.host)
+            .unwrap_or_else(|| server::DEFAULT_HOST.to_string()),
+        port: opts
+            .port
+            .or(config.preview.port)
+            .unwrap_or(server::DEFAULT_PORT),
+        base_url,
+        open: (!opts.no_open).then_some(start_page),
+    };
+    server::launch_server(project_paths.export(), &options)
+}
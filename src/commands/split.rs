@@ -0,0 +1,365 @@
+use std::cell::RefCell;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use clap::Parser;
+
+use crate::context::{Index, IndexEntry};
+use crate::history;
+
+#[derive(Parser)]
+pub struct Opts {
+    /// Code of the page to split.
+    code: String,
+    /// Code of the new page that will hold the second half.
+    new_code: String,
+    /// Title of the new page.
+    ///
+    /// By default, it is the title of the split page followed by
+    /// "(continued)".
+    #[clap(long)]
+    title: Option<String>,
+}
+
+pub fn execute(opts: &Opts) -> Result<()> {
+    let project = crate::paths::project()
+        .context("current directory is not part of a Codasai project")?
+        .canonicalize()
+        .context("failed to canonicalize project directory")?;
+
+    let repo = git2::Repository::open(&project)
+        .with_context(|| format!("failed to open repository at {:?}", &project))?;
+
+    split_page(
+        &project,
+        &repo,
+        &opts.code,
+        &opts.new_code,
+        opts.title.as_deref(),
+        ask,
+    )?;
+    log::info!(
+        "split page `{}` into `{}` and `{}`",
+        &opts.code,
+        &opts.code,
+        &opts.new_code
+    );
+
+    Ok(())
+}
+
+/// Splits the page with the given `code` in the history of `repo` and in the
+/// project at `project` into itself and a new page, `new_code`, titled
+/// `title`.
+///
+/// `ask` answers the questions about which workspace changes of the page stay
+/// in the first half, the others are moved to the new page.
+fn split_page(
+    project: &Path, repo: &git2::Repository, code: &str, new_code: &str, title: Option<&str>,
+    mut ask: impl FnMut(&str) -> Result<bool>,
+) -> Result<()> {
+    let mut index = Index::from_project(project)?;
+    anyhow::ensure!(
+        index.entries.iter().all(|entry| entry.code != new_code),
+        "there is already a page with code `{}`",
+        new_code
+    );
+    let new_page_path = history::page_path(new_code);
+    anyhow::ensure!(
+        !project.join(&new_page_path).exists(),
+        "file {:?} already exists",
+        &new_page_path
+    );
+
+    let chain = history::first_parent_chain(repo)?;
+    let position = history::find_page_commit(repo, &chain, code)?;
+    let commit = repo.find_commit(chain[position])?;
+    let tree = commit.tree()?;
+    let parent = position.checked_sub(1).map(|i| chain[i]);
+    let parent_tree = match parent {
+        Some(parent) => Some(repo.find_commit(parent)?.tree()?),
+        None => None,
+    };
+
+    let page_path = history::page_path(code);
+    let page_blob = tree
+        .get_path(&page_path)?
+        .to_object(repo)?
+        .into_blob()
+        .map_err(|_| anyhow::anyhow!("{:?} is not a file", &page_path))?;
+    let page = String::from_utf8_lossy(page_blob.content()).into_owned();
+    let title = match title {
+        Some(title) => title.to_string(),
+        None => format!("{} (continued)", crate::page::extract_title(&page)),
+    };
+
+    let first_tree = first_half_tree(repo, parent_tree.as_ref(), &tree, &mut ask)?;
+    let first_commit = repo.commit(
+        None,
+        &commit.author(),
+        &commit.committer(),
+        &String::from_utf8_lossy(commit.message_bytes()),
+        &repo.find_tree(first_tree)?,
+        &parent
+            .map(|parent| repo.find_commit(parent))
+            .transpose()?
+            .iter()
+            .collect::<Vec<_>>(),
+    )?;
+
+    let new_page = format!("# {}\n", &title);
+    let new_page_blob = repo.blob(new_page.as_bytes())?;
+    let new_entry = IndexEntry {
+        name: title.clone(),
+        code: new_code.to_string(),
+    };
+    let add_new_page = |tree: &git2::Tree| -> Result<git2::Oid> {
+        let tree = history::tree_with_entry(
+            repo,
+            Some(tree),
+            &new_page_path,
+            Some((new_page_blob, i32::from(git2::FileMode::Blob))),
+        )?;
+        history::tree_with_index(repo, &repo.find_tree(tree)?, |index| {
+            insert_after(index, code, new_entry.clone())
+        })
+    };
+
+    let second_commit = repo.commit(
+        None,
+        &commit.author(),
+        &commit.committer(),
+        &history::page_commit_message(&title, new_code),
+        &repo.find_tree(add_new_page(&tree)?)?,
+        &[&repo.find_commit(first_commit)?],
+    )?;
+
+    let new_head = history::replay(
+        repo,
+        Some(second_commit),
+        &chain[position + 1..],
+        add_new_page,
+    )?
+    .context("failed to rewrite the pages after the split page")?;
+
+    history::update_head(repo, new_head, &format!("codasai: split page {}", code))?;
+
+    insert_after(&mut index, code, new_entry.clone());
+    index.write_to_project(project)?;
+
+    let new_page_file = project.join(&new_page_path);
+    std::fs::write(&new_page_file, &new_page)
+        .with_context(|| format!("failed to write to {:?}", &new_page_file))?;
+
+    history::stage_paths(repo, &[&new_page_path])
+}
+
+/// Builds the tree of the first half of the split page.
+///
+/// `ask` is asked which of the workspace hunks introduced by the page belong
+/// to the first half. Everything outside the workspace is kept as is.
+fn first_half_tree(
+    repo: &git2::Repository, parent_tree: Option<&git2::Tree>, tree: &git2::Tree,
+    ask: &mut impl FnMut(&str) -> Result<bool>,
+) -> Result<git2::Oid> {
+    let mut diff_opts = git2::DiffOptions::new();
+    diff_opts.pathspec("workspace/");
+    let diff = repo.diff_tree_to_tree(parent_tree, Some(tree), Some(&mut diff_opts))?;
+
+    // hunks selected for each delta in the diff, deltas without hunks (e.g.
+    // binary files) are selected as a whole
+    let mut selections: Vec<Vec<bool>> = Vec::new();
+    for (i, delta) in diff.deltas().enumerate() {
+        let file = delta.new_file().path().or_else(|| delta.old_file().path());
+        let file = file
+            .context("changed file without a path")?
+            .display()
+            .to_string();
+
+        let patch = git2::Patch::from_diff(&diff, i)?;
+        match patch {
+            Some(patch) if patch.num_hunks() > 0 => {
+                let mut hunks = Vec::new();
+                for hunk_idx in 0..patch.num_hunks() {
+                    let (hunk, lines) = patch.hunk(hunk_idx)?;
+                    println!("\n{}", file);
+                    print!("{}", String::from_utf8_lossy(hunk.header()));
+                    for line_idx in 0..lines {
+                        let line = patch.line_in_hunk(hunk_idx, line_idx)?;
+                        print!(
+                            "{}{}",
+                            line.origin(),
+                            String::from_utf8_lossy(line.content())
+                        );
+                    }
+                    hunks.push(ask("Keep this hunk in the first half?")?);
+                }
+                selections.push(hunks);
+            },
+            _ => {
+                println!("\n{}", file);
+                selections.push(vec![ask("Keep this file in the first half?")?]);
+            },
+        }
+    }
+
+    // start from the split page's tree with the previous workspace and apply
+    // the selected hunks on top of it
+    let base = match parent_tree {
+        Some(parent_tree) => {
+            history::tree_with_entry_from(repo, Some(tree), Path::new("workspace"), parent_tree)?
+        },
+        None => history::tree_with_entry(repo, Some(tree), Path::new("workspace"), None)?,
+    };
+    let base = repo.find_tree(base)?;
+
+    let delta_idx = RefCell::new(0);
+    let hunk_idx = RefCell::new(0);
+    let mut apply_opts = git2::ApplyOptions::new();
+    apply_opts.delta_callback(|_| {
+        let delta = *delta_idx.borrow();
+        *delta_idx.borrow_mut() += 1;
+        *hunk_idx.borrow_mut() = 0;
+        selections[delta].iter().any(|selected| *selected)
+    });
+    apply_opts.hunk_callback(|_| {
+        let delta = *delta_idx.borrow() - 1;
+        let hunk = *hunk_idx.borrow();
+        *hunk_idx.borrow_mut() += 1;
+        selections[delta][hunk]
+    });
+
+    let mut index = repo
+        .apply_to_tree(&base, &diff, Some(&mut apply_opts))
+        .context("failed to apply the selected hunks")?;
+
+    Ok(index.write_tree_to(repo)?)
+}
+
+/// Inserts `entry` right after the entry with the given `code`.
+fn insert_after(index: &mut Index, code: &str, entry: IndexEntry) {
+    if let Some(position) = index.entries.iter().position(|e| e.code == code) {
+        index.entries.insert(position + 1, entry);
+    }
+}
+
+/// Asks a yes/no question through the standard input.
+fn ask(question: &str) -> Result<bool> {
+    loop {
+        print!("{} [y/n] ", question);
+        std::io::stdout().flush()?;
+
+        let mut answer = String::new();
+        std::io::stdin()
+            .read_line(&mut answer)
+            .context("failed to read answer")?;
+        match answer.trim() {
+            "y" | "Y" => return Ok(true),
+            "n" | "N" => return Ok(false),
+            _ => continue,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestRepo;
+
+    /// Returns lines `1` to `20` with the first and the last ones replaced
+    /// if `first` and `last` are set.
+    fn lines(first: Option<&str>, last: Option<&str>) -> String {
+        let mut lines = (1..=20).map(|i| i.to_string()).collect::<Vec<_>>();
+        if let Some(first) = first {
+            lines[0] = first.to_string();
+        }
+        if let Some(last) = last {
+            lines[19] = last.to_string();
+        }
+        lines.join("\n") + "\n"
+    }
+
+    fn codes(test: &TestRepo, head: git2::Oid) -> Vec<String> {
+        let history = history::page_history(&test.repo, head).unwrap();
+        history.pages.into_iter().map(|page| page.code).collect()
+    }
+
+    #[test]
+    fn splits_a_page_in_the_middle() {
+        let three = lines(Some("first"), Some("last")) + "21\n";
+        let test = TestRepo::guide(&[
+            ("one", &lines(None, None)),
+            ("two", &lines(Some("first"), Some("last"))),
+            ("three", &three),
+        ]);
+
+        // the first hunk is kept in the first half, the second one is moved
+        let mut answers = vec![false, true];
+        split_page(
+            test.path(),
+            &test.repo,
+            "two",
+            "two-b",
+            Some("Two b"),
+            |_| Ok(answers.pop().unwrap()),
+        )
+        .unwrap();
+        assert!(answers.is_empty());
+
+        let chain = history::first_parent_chain(&test.repo).unwrap();
+        assert_eq!(chain.len(), 5);
+        assert_eq!(codes(&test, chain[4]), ["one", "two", "two-b", "three"]);
+        assert_eq!(
+            test.file(chain[2], "workspace/main.rs").unwrap(),
+            lines(Some("first"), None)
+        );
+        assert_eq!(test.file(chain[2], "pages/two-b.md"), None);
+        assert_eq!(
+            test.file(chain[3], "workspace/main.rs").unwrap(),
+            lines(Some("first"), Some("last"))
+        );
+        assert_eq!(test.file(chain[3], "pages/two-b.md").unwrap(), "# Two b\n");
+        assert_eq!(test.file(chain[4], "workspace/main.rs").unwrap(), three);
+        assert!(test.file(chain[4], "pages/two-b.md").is_some());
+
+        let index = Index::from_project(test.path()).unwrap();
+        let index_codes = index.entries.iter().map(|entry| entry.code.as_str());
+        assert!(index_codes.eq(["one", "two", "two-b", "three"]));
+        assert!(test.path().join("pages/two-b.md").is_file());
+        assert!(test.changes().is_empty());
+    }
+
+    #[test]
+    fn splits_the_last_page() {
+        let test = TestRepo::guide(&[("one", "1\n"), ("two", "1\n2\n")]);
+
+        let mut questions = 0;
+        split_page(test.path(), &test.repo, "two", "two-b", None, |_| {
+            questions += 1;
+            Ok(false)
+        })
+        .unwrap();
+        assert_eq!(questions, 1);
+
+        let chain = history::first_parent_chain(&test.repo).unwrap();
+        assert_eq!(chain.len(), 4);
+        assert_eq!(codes(&test, chain[3]), ["one", "two", "two-b"]);
+        assert_eq!(test.file(chain[2], "workspace/main.rs").unwrap(), "1\n");
+        assert_eq!(test.file(chain[3], "workspace/main.rs").unwrap(), "1\n2\n");
+        assert_eq!(
+            test.file(chain[3], "pages/two-b.md").unwrap(),
+            "# two (continued)\n"
+        );
+        assert!(test.path().join("pages/two-b.md").is_file());
+        assert!(test.changes().is_empty());
+    }
+
+    #[test]
+    fn new_code_must_be_unused() {
+        let test = TestRepo::guide(&[("one", "1"), ("two", "2")]);
+        let error =
+            split_page(test.path(), &test.repo, "one", "two", None, |_| Ok(true)).unwrap_err();
+        assert!(error.to_string().contains("already a page"));
+    }
+}
@@ -0,0 +1,284 @@
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand};
+
+use crate::history;
+use crate::paths::ProjectPaths;
+use crate::theme::Theme;
+
+#[derive(Parser)]
+pub struct Opts {
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Shows how the project's theme differs from the theme it inherits.
+    ///
+    /// It inherits the installed theme, if any, and the built-in theme.
+    Diff,
+    /// Merges the changes of the inherited theme into the files the project's
+    /// theme overrides.
+    ///
+    /// Files that conflict are left with conflict markers.
+    Update,
+    /// Copies a file of the inherited theme to the project's theme so that it
+    /// can be overridden.
+    Eject(EjectOpts),
+}
+
+#[derive(Parser)]
+struct EjectOpts {
+    /// Path of the file relative to the theme's root, e.g.
+    /// `templates/page.html`.
+    file: PathBuf,
+}
+
+pub fn execute(opts: &Opts) -> Result<()> {
+    let project_paths = ProjectPaths::new()?;
+
+    match &opts.command {
+        Command::Diff => diff(&project_paths),
+        Command::Update => update(&project_paths),
+        Command::Eject(eject_opts) => eject(&project_paths, &eject_opts.file),
+    }
+}
+
+/// Prints a patch from the inherited theme to the project's theme.
+fn diff(project_paths: &ProjectPaths) -> Result<()> {
+    let theme = Theme::from_project(project_paths.project())?;
+    let mut differs = false;
+
+    for path in theme.overrides()? {
+        let contents = read(&project_paths.theme().join(&path))?;
+        let inherited = theme.inherited_file(&path)?;
+        if inherited.as_deref() == Some(contents.as_slice()) {
+            continue;
+        }
+
+        let mut patch = git2::Patch::from_buffers(
+            inherited.as_deref().unwrap_or_default(),
+            inherited.as_ref().map(|_| path.as_path()),
+            &contents,
+            Some(&path),
+            None,
+        )
+        .with_context(|| format!("failed to diff {:?}", &path))?;
+        print!("{}", String::from_utf8_lossy(&patch.to_buf()?));
+        differs = true;
+    }
+
+    if !differs {
+        log::info!("the project's theme doesn't differ from the inherited theme");
+    }
+
+    Ok(())
+}
+
+/// Merges the changes of the inherited theme since the last update into the
+/// project's theme.
+///
+/// The inherited version of every overridden file is kept in the theme base as
+/// the common ancestor of the next merge.
+fn update(project_paths: &ProjectPaths) -> Result<()> {
+    let repo = git2::Repository::open(project_paths.project())
+        .with_context(|| format!("failed to open repository at {:?}", project_paths.project()))?;
+    let theme = Theme::from_project(project_paths.project())?;
+    let mut updated = 0;
+    let mut conflicts = Vec::new();
+
+    for path in theme.overrides()? {
+        let base_path = project_paths.theme_base().join(&path);
+        let base = if base_path.is_file() {
+            Some(read(&base_path)?)
+        } else {
+            None
+        };
+        let inherited = match theme.inherited_file(&path)? {
+            Some(inherited) => inherited,
+            None => {
+                if base.is_some() {
+                    log::warn!("{:?} was removed from the inherited theme", &path);
+                }
+                continue;
+            },
+        };
+        let ours_path = project_paths.theme().join(&path);
+        let ours = read(&ours_path)?;
+
+        let base = match base {
+            Some(base) => base,
+            // files copied before the theme base existed can only be tracked
+            // if they weren't changed
+            None if ours == *inherited => {
+                write(&base_path, &inherited)?;
+                continue;
+            },
+            None => {
+                log::warn!(
+                    "{:?} can't be updated because its inherited version is unknown, see \
+                     `codasai theme diff`",
+                    &path
+                );
+                continue;
+            },
+        };
+
+        if base == *inherited {
+            continue;
+        }
+
+        if ours == base {
+            write(&ours_path, &inherited)?;
+        } else if merge_file(
+            &repo,
+            project_paths.theme(),
+            &path,
+            &base,
+            &ours,
+            &inherited,
+        )? {
+            conflicts.push(path.to_string_lossy().into_owned());
+        }
+        write(&base_path, &inherited)?;
+        updated += 1;
+    }
+
+    anyhow::ensure!(
+        conflicts.is_empty(),
+        "the inherited theme's changes conflict with the project's theme in:\n\n    {}\n\nresolve \
+         the conflict markers in `.codasai/theme/`",
+        conflicts.join("\n    ")
+    );
+
+    if updated == 0 {
+        log::info!("the project's theme is already up to date");
+    } else {
+        log::info!("updated {} files of the project's theme", updated);
+    }
+
+    Ok(())
+}
+
+/// Merges `base`, `ours` and `theirs` into the file at `path` in `theme_dir`.
+///
+/// Returns whether the file has conflicts, which are written as conflict
+/// markers.
+fn merge_file(
+    repo: &git2::Repository, theme_dir: &Path, path: &Path, base: &[u8], ours: &[u8], theirs: &[u8],
+) -> Result<bool> {
+    let tree = |contents: &[u8]| -> Result<git2::Tree> {
+        let blob = repo.blob(contents)?;
+        let tree = history::tree_with_entry(
+            repo,
+            None,
+            path,
+            Some((blob, i32::from(git2::FileMode::Blob))),
+        )?;
+        Ok(repo.find_tree(tree)?)
+    };
+
+    let mut merged = repo
+        .merge_trees(&tree(base)?, &tree(ours)?, &tree(theirs)?, None)
+        .with_context(|| format!("failed to merge {:?}", path))?;
+    let has_conflicts = merged.has_conflicts();
+
+    repo.checkout_index(
+        Some(&mut merged),
+        Some(
+            git2::build::CheckoutBuilder::new()
+                .target_dir(theme_dir)
+                .path(path)
+                .force()
+                // the merged index only holds this file, it isn't the
+                // repository's index
+                .update_index(false)
+                .allow_conflicts(true)
+                .conflict_style_merge(true)
+                .ancestor_label("base")
+                .our_label("project")
+                .their_label("inherited"),
+        ),
+    )
+    .with_context(|| format!("failed to write the merged {:?}", path))?;
+
+    Ok(has_conflicts)
+}
+
+/// Copies the inherited `file` to the project's theme and records it in the
+/// theme base.
+fn eject(project_paths: &ProjectPaths, file: &Path) -> Result<()> {
+    anyhow::ensure!(
+        file.components().all(|c| matches!(c, Component::Normal(_))),
+        "{:?} must be relative to the theme's root, e.g. `templates/page.html`",
+        file
+    );
+    let theme = Theme::from_project(project_paths.project())?;
+    let inherited = theme
+        .inherited_file(file)?
+        .ok_or_else(|| anyhow::anyhow!("the inherited theme doesn't have {:?}", file))?;
+
+    let out_path = project_paths.theme().join(file);
+    anyhow::ensure!(
+        !out_path.exists(),
+        "{:?} is already overridden by the project's theme",
+        file
+    );
+
+    write(&out_path, &inherited)?;
+    write(&project_paths.theme_base().join(file), &inherited)?;
+    log::info!("copied {:?} to {:?}", file, &out_path);
+
+    Ok(())
+}
+
+fn read(path: &Path) -> Result<Vec<u8>> {
+    std::fs::read(path).with_context(|| format!("failed to read {:?}", path))
+}
+
+fn write(path: &Path, contents: &[u8]) -> Result<()> {
+    let parent = path.parent().unwrap();
+    std::fs::create_dir_all(parent)
+        .with_context(|| format!("failed to create directory {:?}", parent))?;
+    std::fs::write(path, contents).with_context(|| format!("failed to write to {:?}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestRepo;
+
+    #[test]
+    fn merged_files_keep_both_sides_and_mark_conflicts() {
+        let test = TestRepo::new();
+        let theme_dir = tempfile::tempdir().unwrap();
+        let path = Path::new("templates/page.html");
+        let merge = |ours: &str, theirs: &str| {
+            let has_conflicts = merge_file(
+                &test.repo,
+                theme_dir.path(),
+                path,
+                b"a\nb\nc\n",
+                ours.as_bytes(),
+                theirs.as_bytes(),
+            )
+            .unwrap();
+            let merged = std::fs::read_to_string(theme_dir.path().join(path)).unwrap();
+            (has_conflicts, merged)
+        };
+
+        assert_eq!(
+            merge("A\nb\nc\n", "a\nb\nC\n"),
+            (false, "A\nb\nC\n".to_string())
+        );
+        assert_eq!(
+            merge("a\nB\nc\n", "a\nX\nc\n"),
+            (
+                true,
+                "a\n<<<<<<< project\nB\n=======\nX\n>>>>>>> inherited\nc\n".to_string()
+            )
+        );
+    }
+}
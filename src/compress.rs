@@ -0,0 +1,54 @@
+use std::ffi::OsStr;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use flate2::write::GzEncoder;
+use walkdir::WalkDir;
+
+/// Extensions of the files worth compressing, other files like images are
+/// compressed already.
+const COMPRESSIBLE_EXTENSIONS: [&str; 8] =
+    ["css", "html", "js", "json", "svg", "txt", "xml", "map"];
+
+/// Writes gzip and brotli compressed copies of the text files in `dir` next to
+/// them, e.g. `index.html.gz` and `index.html.br`, which static hosts can
+/// serve to browsers that accept them.
+pub fn precompress(dir: &Path) -> Result<()> {
+    for entry in WalkDir::new(dir).sort_by_file_name() {
+        let entry = entry.with_context(|| format!("failed to read directory {:?}", dir))?;
+        let path = entry.path();
+        let is_compressible = path
+            .extension()
+            .and_then(OsStr::to_str)
+            .is_some_and(|extension| COMPRESSIBLE_EXTENSIONS.contains(&extension));
+        if !entry.file_type().is_file() || !is_compressible {
+            continue;
+        }
+
+        let contents =
+            std::fs::read(path).with_context(|| format!("failed to read file {:?}", path))?;
+
+        let mut gzip = GzEncoder::new(Vec::new(), flate2::Compression::best());
+        gzip.write_all(&contents)?;
+        write_compressed(path, "gz", &gzip.finish()?)?;
+
+        // the largest window and the best quality, which is slow but only
+        // done once
+        let mut brotli = brotli::CompressorWriter::new(Vec::new(), 4096, 11, 24);
+        brotli.write_all(&contents)?;
+        write_compressed(path, "br", &brotli.into_inner())?;
+    }
+
+    Ok(())
+}
+
+/// Writes `compressed` to the path of the file at `path` followed by
+/// `extension`.
+fn write_compressed(path: &Path, extension: &str, compressed: &[u8]) -> Result<()> {
+    let mut out_path = path.as_os_str().to_os_string();
+    out_path.push(".");
+    out_path.push(extension);
+    std::fs::write(&out_path, compressed)
+        .with_context(|| format!("failed to write to {:?}", &out_path))
+}
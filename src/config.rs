@@ -0,0 +1,290 @@
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+/// Files and directories at the root of the export directory of a guide with
+/// variants, which variants can't be named after.
+const RESERVED_VARIANT_NAMES: [&str; 3] = ["public", "index.html", "404.html"];
+
+/// A structure used to hold a guide's configuration.
+///
+/// It is read from `.codasai/guide.toml`.
+#[derive(Deserialize)]
+pub struct Config {
+    pub title: String,
+    #[serde(default)]
+    pub variants: Vec<VariantConfig>,
+    #[serde(default)]
+    pub save: SaveConfig,
+    #[serde(default)]
+    pub new: NewConfig,
+    #[serde(default)]
+    pub preview: PreviewConfig,
+    #[serde(default)]
+    pub theme: ThemeConfig,
+    #[serde(default)]
+    pub highlight: HighlightConfig,
+}
+
+impl Config {
+    pub fn from_project(project: &Path) -> Result<Self> {
+        let config_path = project.join(".codasai/guide.toml");
+        let config_toml = std::fs::read_to_string(&config_path)
+            .with_context(|| format!("failed to read config file {:?}", &config_path))?;
+        let config: Self = toml::from_str(&config_toml)
+            .with_context(|| format!("failed to deserialize config at {:?}", &config_path))?;
+
+        for (i, variant) in config.variants.iter().enumerate() {
+            variant
+                .validate()
+                .with_context(|| format!("invalid variant in {:?}", &config_path))?;
+            anyhow::ensure!(
+                config.variants[..i]
+                    .iter()
+                    .all(|other| other.name != variant.name),
+                "there are multiple variants called `{}` in {:?}",
+                &variant.name,
+                &config_path
+            );
+        }
+
+        Ok(config)
+    }
+}
+
+/// A variant of the guide built from its own git reference.
+#[derive(Deserialize)]
+pub struct VariantConfig {
+    /// Name of the variant, used as the subdirectory it is exported to.
+    pub name: String,
+    /// Title shown in the page that lists the variants.
+    ///
+    /// By default, it is the variant's name.
+    pub title: Option<String>,
+    /// Git reference (branch, tag or commit) the variant is built from.
+    #[serde(rename = "ref")]
+    pub reference: String,
+}
+
+impl VariantConfig {
+    /// Checks that the variant's name can be used as the name of its
+    /// subdirectory and in its url.
+    ///
+    /// The export directory of every variant is removed before it's built, so
+    /// a name like `..` would remove the directories around it.
+    fn validate(&self) -> Result<()> {
+        let mut components = Path::new(&self.name).components();
+        let is_single_directory = matches!(
+            (components.next(), components.next()),
+            (Some(Component::Normal(_)), None)
+        );
+        anyhow::ensure!(
+            is_single_directory
+                && !self.name.contains(
+                    |c: char| matches!(c, '/' | '\\' | '?' | '#' | '%') || c.is_whitespace()
+                ),
+            "`{}` is not a valid variant name, it must be a single directory name without \
+             spaces or any of `/\\?#%`",
+            &self.name
+        );
+        anyhow::ensure!(
+            !RESERVED_VARIANT_NAMES.contains(&self.name.as_str()),
+            "`{}` is not a valid variant name, it is the name of a file exported with the \
+             variants, like {}",
+            &self.name,
+            RESERVED_VARIANT_NAMES
+                .iter()
+                .map(|name| format!("`{}`", name))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        Ok(())
+    }
+}
+
+/// Configuration used by `codasai save`.
+#[derive(Default, Deserialize)]
+pub struct SaveConfig {
+    /// Author of the page commits.
+    ///
+    /// By default, it is the identity set in git's configuration.
+    pub author: Option<Identity>,
+    /// Committer of the page commits.
+    ///
+    /// By default, it is Codasai CLI.
+    pub committer: Option<Identity>,
+    /// Command run in the workspace directory before saving a page.
+    ///
+    /// The page isn't saved if the command fails.
+    pub check: Option<String>,
+}
+
+impl SaveConfig {
+    /// Returns the signature used as the author of page commits.
+    pub fn author_signature(&self, repo: &git2::Repository) -> Result<git2::Signature<'static>> {
+        match &self.author {
+            Some(author) => author.to_signature(),
+            None => repo.signature().context(
+                "failed to read author from git configuration, set `user.name` and `user.email` \
+                 or `save.author` in `.codasai/guide.toml`",
+            ),
+        }
+    }
+
+    /// Returns the signature used as the committer of page commits.
+    pub fn committer_signature(&self) -> Result<git2::Signature<'static>> {
+        match &self.committer {
+            Some(committer) => committer.to_signature(),
+            None => Identity::codasai().to_signature(),
+        }
+    }
+}
+
+/// Configuration used by `codasai new`.
+#[derive(Default, Deserialize)]
+pub struct NewConfig {
+    /// Template used to create new pages, relative to `.codasai/`.
+    ///
+    /// By default, it is `page-template.md`.
+    pub template: Option<PathBuf>,
+}
+
+/// Configuration used by `codasai preview`.
+///
+/// The command line options take precedence over it.
+#[derive(Default, Deserialize)]
+pub struct PreviewConfig {
+    /// Host name or IP address the server listens on.
+    ///
+    /// By default, it is `127.0.0.1`.
+    pub host: Option<String>,
+    /// Port the server listens on.
+    ///
+    /// By default, it is 8000. A free port is used if it is taken.
+    pub port: Option<u16>,
+    /// URL path the preview is served at.
+    ///
+    /// By default, it is `/`.
+    pub base_url: Option<String>,
+}
+
+/// Configuration of the guide's theme.
+#[derive(Default, Deserialize)]
+pub struct ThemeConfig {
+    /// Installed theme the project's theme inherits from, relative to the
+    /// project.
+    ///
+    /// It is either a directory or a tar archive. By default, the project's
+    /// theme only inherits from the built-in theme.
+    pub path: Option<PathBuf>,
+    /// Values of the options exposed by the installed theme.
+    #[serde(default)]
+    pub options: toml::value::Table,
+}
+
+/// Configuration of the syntax highlighting of code.
+#[derive(Default, Deserialize)]
+pub struct HighlightConfig {
+    /// Syntax theme used when the reader prefers light colors.
+    ///
+    /// By default, it is `inspired-github`.
+    pub light_theme: Option<String>,
+    /// Syntax theme used when the reader prefers dark colors.
+    ///
+    /// By default, it is `ayu-dark`.
+    pub dark_theme: Option<String>,
+    /// Syntaxes used for file extensions, e.g. `zon = "zig"`.
+    ///
+    /// Syntaxes are referred to by their name or one of their extensions.
+    #[serde(default)]
+    pub extensions: BTreeMap<String, String>,
+    /// Syntaxes used for file names, e.g. `Justfile = "make"`.
+    #[serde(default)]
+    pub filenames: BTreeMap<String, String>,
+    /// Syntaxes used for files matching glob patterns relative to the
+    /// workspace.
+    ///
+    /// They take precedence over the other mappings. If several globs match a
+    /// file, the first one in the list wins.
+    #[serde(default)]
+    pub globs: Vec<GlobSyntax>,
+}
+
+impl HighlightConfig {
+    /// Returns the name of the light syntax theme.
+    pub fn light_theme(&self) -> &str {
+        self.light_theme.as_deref().unwrap_or("inspired-github")
+    }
+
+    /// Returns the name of the dark syntax theme.
+    pub fn dark_theme(&self) -> &str {
+        self.dark_theme.as_deref().unwrap_or("ayu-dark")
+    }
+}
+
+/// A syntax used for the files matching a glob pattern, e.g.
+/// `{ glob = "scripts/*", syntax = "bash" }`.
+#[derive(Deserialize)]
+pub struct GlobSyntax {
+    /// Pattern matched against paths relative to the workspace.
+    pub glob: String,
+    /// Name or extension of the syntax.
+    pub syntax: String,
+}
+
+/// A name and email pair used to sign commits.
+#[derive(Clone, Deserialize)]
+pub struct Identity {
+    pub name: String,
+    pub email: String,
+}
+
+impl Identity {
+    /// Identity used for commits made by Codasai itself.
+    pub fn codasai() -> Self {
+        Self {
+            name: "Codasai CLI".to_string(),
+            email: "codasai.cli@gmail.com".to_string(),
+        }
+    }
+
+    pub fn to_signature(&self) -> Result<git2::Signature<'static>> {
+        git2::Signature::now(&self.name, &self.email)
+            .with_context(|| format!("invalid identity {} <{}>", &self.name, &self.email))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn variant_names_must_be_directory_names() {
+        let validate = |name: &str| {
+            VariantConfig {
+                name: name.to_string(),
+                title: None,
+                reference: "main".to_string(),
+            }
+            .validate()
+        };
+        validate("python").unwrap();
+        validate("v1.0").unwrap();
+        for name in [
+            "",
+            ".",
+            "..",
+            "a/b",
+            "a\\b",
+            "a b",
+            "a?",
+            "public",
+            "index.html",
+            "404.html",
+        ] {
+            assert!(validate(name).is_err(), "{:?}", name);
+        }
+    }
+}
@@ -1,10 +1,18 @@
 use serde::Serialize;
 
 use super::Index;
+use crate::export::{AssetManifest, BuildMode};
 
 /// Context used to pass a guide's data to the front-end
 #[derive(Serialize)]
 pub struct GuideContext {
     pub index: Index,
     pub base_url: String,
+    /// Fingerprinted copies of the guide's public files, which template
+    /// functions link to instead of the original files.
+    #[serde(skip)]
+    pub assets: AssetManifest,
+    /// Whether the guide's pages are minified.
+    #[serde(skip)]
+    pub mode: BuildMode,
 }
@@ -21,6 +21,23 @@ impl Index {
             .with_context(|| format!("failed to deserialize index at {:?}", &index_path))
     }
 
+    /// Reads the index tracked in `tree`, if any.
+    pub fn from_tree(repo: &git2::Repository, tree: &git2::Tree) -> Result<Option<Self>> {
+        let entry = match tree.get_path(Path::new(".codasai/index.toml")) {
+            Ok(entry) => entry,
+            Err(_) => return Ok(None),
+        };
+        let object = entry.to_object(repo)?;
+        let blob = object
+            .as_blob()
+            .ok_or_else(|| anyhow::anyhow!("index in tree {} is not a file", tree.id()))?;
+        let index_toml = std::str::from_utf8(blob.content())
+            .with_context(|| format!("index in tree {} is not valid utf-8", tree.id()))?;
+        let index = toml::from_str(index_toml)
+            .with_context(|| format!("failed to deserialize index in tree {}", tree.id()))?;
+        Ok(Some(index))
+    }
+
     pub fn write_to_project(&self, project: &Path) -> Result<()> {
         let index_path = project.join(".codasai/index.toml");
         std::fs::write(&index_path, toml::to_string_pretty(self)?)
@@ -1,13 +1,17 @@
 mod guide;
 mod index;
+mod not_found;
 mod page;
+mod variants;
 mod workspace;
 
 pub use guide::GuideContext;
 pub use index::{Index, IndexEntry};
+pub use not_found::NotFoundContext;
 pub use page::PageContext;
 use serde::Serialize;
-pub use workspace::{Directory, File, WorkspaceOutlineBuilder};
+pub use variants::{VariantEntry, VariantsContext};
+pub use workspace::{Directory, WorkspaceOutlineBuilder};
 
 #[derive(Serialize)]
 pub struct GlobalContext<'a> {
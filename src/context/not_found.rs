@@ -0,0 +1,11 @@
+use serde::Serialize;
+
+use super::GuideContext;
+
+/// Context used to pass the data of the page shown for missing files to the
+/// front-end
+#[derive(Serialize)]
+pub struct NotFoundContext<'a> {
+    pub title: String,
+    pub guide: &'a GuideContext,
+}
@@ -9,6 +9,10 @@ pub struct PageContext {
     pub title: String,
     pub code: String,
     pub content: String,
+    /// Time the page was saved at, in seconds since the Unix epoch.
+    ///
+    /// Unsaved pages use the current time.
+    pub date: i64,
     pub workspace: Directory,
     pub previous_page_code: Option<String>,
     pub next_page_code: Option<String>,
@@ -0,0 +1,15 @@
+use serde::Serialize;
+
+/// Context used to pass a guide's variants to the front-end
+#[derive(Serialize)]
+pub struct VariantsContext {
+    pub title: String,
+    pub base_url: String,
+    pub variants: Vec<VariantEntry>,
+}
+
+#[derive(Serialize)]
+pub struct VariantEntry {
+    pub name: String,
+    pub title: String,
+}
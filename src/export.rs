@@ -1,108 +1,336 @@
+use std::collections::BTreeMap;
 use std::ffi::OsStr;
 use std::path::Path;
+use std::sync::Arc;
 
 use anyhow::{Context, Result};
-use syntect::highlighting::ThemeSet;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use syntect::html::css_for_theme_with_class_style;
 use walkdir::WalkDir;
 
+use crate::config::Config;
+use crate::context::{GuideContext, NotFoundContext};
 use crate::paths::ProjectPaths;
+use crate::theme::Theme;
+
+/// File, in the exported `public/` directory, that maps public files to their
+/// fingerprinted copies.
+const ASSET_MANIFEST_FILE: &str = "assets.json";
+
+/// How the guide's files are exported.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum BuildMode {
+    /// Files are exported as they are written, which is easier to debug.
+    #[default]
+    Development,
+    /// Html is minified, css is compressed and javascript modules are
+    /// bundled, so that readers download less.
+    Production,
+}
+
+impl BuildMode {
+    /// Returns the html document `html`, minified in production.
+    pub fn html(self, html: String) -> String {
+        match self {
+            BuildMode::Development => html,
+            BuildMode::Production => crate::minify::html(&html),
+        }
+    }
+}
+
+/// Maps the paths of public files, relative to `public/`, to the paths of
+/// their fingerprinted copies, whose names have a hash of their contents.
+///
+/// Fingerprinted files can be cached by browsers forever since they get
+/// another name when they change, unlike the files they refer to by relative
+/// paths.
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct AssetManifest(BTreeMap<String, String>);
+
+impl AssetManifest {
+    /// Returns the path of the fingerprinted copy of the public file at
+    /// `path`, if any.
+    pub fn get(&self, path: &str) -> Option<&str> {
+        self.0.get(path.trim_start_matches('/')).map(String::as_str)
+    }
+
+    /// Replaces the public file in `url_path`, a path relative to the guide's
+    /// root like `public/theme/style/style.css`, with its fingerprinted copy.
+    ///
+    /// Paths that don't point to a fingerprinted file are returned as is.
+    pub fn resolve(&self, url_path: &str) -> String {
+        let relative_path = url_path.trim_start_matches('/');
+        let (path, suffix) = match relative_path.find(['?', '#']) {
+            Some(i) => relative_path.split_at(i),
+            None => (relative_path, ""),
+        };
+
+        match path.strip_prefix("public/").and_then(|path| self.get(path)) {
+            Some(fingerprinted) => format!("public/{}{}", fingerprinted, suffix),
+            None => url_path.to_string(),
+        }
+    }
+}
 
 /// Takes care of exporting all files needed by the guide such as images, css,
 /// etc.
-pub fn export_public_files(project: &ProjectPaths) -> Result<()> {
+pub fn export_public_files(project: &ProjectPaths, theme: &Theme, mode: BuildMode) -> Result<()> {
     if project.export().exists() {
-        std::fs::remove_dir_all(&project.export()).context("failed to remove export directory")?;
+        std::fs::remove_dir_all(project.export()).context("failed to remove export directory")?;
     }
 
     export_user_static_dir(project).context("failed to export public directory")?;
-    copy_theme_static_dir(project).context("failed to export theme public directory")?;
-    compile_sass(project).context("failed to render sass files")?;
-    compile_syntax_themes(project).context("failed to compile syntax themes")?;
+    copy_theme_static_dir(project, theme).context("failed to export theme public directory")?;
+    compile_sass(project, theme, mode).context("failed to render sass files")?;
+    compile_syntax_themes(project, theme).context("failed to compile syntax themes")?;
+
+    if mode == BuildMode::Production {
+        // only the theme's modules are bundled, the user's public files are
+        // exported as they are
+        crate::bundle::bundle_modules(&project.export().join("public/theme"))
+            .context("failed to bundle javascript modules")?;
+    }
 
     Ok(())
 }
 
-/// Compiles the `.thTheme` provided by the project's theme.
-fn compile_syntax_themes(project: &ProjectPaths) -> Result<()> {
-    let themes_dir = project.theme().join("syntax");
+/// Copies every exported public file to a path with a hash of its contents,
+/// e.g. `theme/style/style.1a2b3c4d.css`, and writes the manifest of the
+/// copies to `public/assets.json`.
+///
+/// References between public files aren't rewritten, so the copies of
+/// stylesheets and scripts load the files they refer to by relative paths
+/// from the original paths, which are kept. Only the contents of the copies
+/// themselves are immutable.
+pub fn fingerprint_public_files(project: &ProjectPaths) -> Result<AssetManifest> {
+    let public_dir = project.export().join("public");
+    let mut manifest = AssetManifest::default();
+
+    // the files are listed beforehand so that the copies aren't fingerprinted
+    let mut files = Vec::new();
+    for entry in WalkDir::new(&public_dir).sort_by_file_name() {
+        let entry = entry.with_context(|| format!("failed to read directory {:?}", &public_dir))?;
+        if entry.file_type().is_file() {
+            files.push(entry.into_path());
+        }
+    }
+
+    for path in &files {
+        let relative_path = path.strip_prefix(&public_dir)?;
+
+        let contents =
+            std::fs::read(path).with_context(|| format!("failed to read file {:?}", path))?;
+        let hash = content_hash(&contents);
+
+        let file_name = match path.extension() {
+            Some(extension) => format!(
+                "{}.{}.{}",
+                path.file_stem().unwrap().to_string_lossy(),
+                &hash,
+                extension.to_string_lossy()
+            ),
+            None => format!("{}.{}", path.file_name().unwrap().to_string_lossy(), &hash),
+        };
+        let fingerprinted_path = relative_path.with_file_name(file_name);
+        let out_path = public_dir.join(&fingerprinted_path);
+        std::fs::write(&out_path, &contents)
+            .with_context(|| format!("failed to write to {:?}", &out_path))?;
+
+        manifest
+            .0
+            .insert(url_path(relative_path), url_path(&fingerprinted_path));
+    }
+
+    let manifest_path = public_dir.join(ASSET_MANIFEST_FILE);
+    let manifest_json = serde_json::to_string_pretty(&manifest)?;
+    std::fs::write(&manifest_path, manifest_json)
+        .with_context(|| format!("failed to write to {:?}", &manifest_path))?;
+
+    Ok(manifest)
+}
+
+/// Returns a short hash of `contents`, the first hex digits of its SHA-256
+/// digest, which is the same on every platform and codasai version.
+pub fn content_hash(contents: &[u8]) -> String {
+    Sha256::digest(contents)[..4]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Converts `path` to a path with `/` separators for urls.
+fn url_path(path: &Path) -> String {
+    path.components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Exports the page shown for missing files to `404.html` in the export
+/// directory, where static hosts look for it.
+///
+/// Themes that don't provide `404.html` don't get the page exported.
+pub fn export_not_found_page(
+    project: &ProjectPaths, theme: &Arc<Theme>, guide: &GuideContext,
+) -> Result<()> {
+    let html = match render_not_found_page(project.project(), theme, guide)? {
+        Some(html) => html,
+        None => {
+            log::warn!("the theme doesn't provide `404.html`, no 404 page will be exported");
+            return Ok(());
+        },
+    };
+
+    let out_path = project.export().join("404.html");
+    std::fs::write(&out_path, guide.mode.html(html))
+        .with_context(|| format!("failed to write to {:?}", &out_path))
+}
+
+/// Renders the page shown for missing files with `404.html` in `theme`, the
+/// project's theme.
+///
+/// Returns `None` if the theme doesn't provide `404.html`.
+pub fn render_not_found_page(
+    project: &Path, theme: &Arc<Theme>, guide: &GuideContext,
+) -> Result<Option<String>> {
+    let config = Config::from_project(project)?;
+    let templates = crate::page::read_theme_templates(project, theme, guide)
+        .context("failed to read templates")?;
+    let template = match templates.get_template("404.html") {
+        Ok(template) => template,
+        Err(e) if e.kind() == minijinja::ErrorKind::TemplateNotFound => return Ok(None),
+        Err(e) => return Err(e).context("failed to read `404.html`"),
+    };
+
+    let context = NotFoundContext {
+        title: config.title,
+        guide,
+    };
+    template
+        .render(&context)
+        .map(Some)
+        .context("failed to render `404.html`")
+}
+
+/// Compiles the light and dark syntax themes configured for the project.
+fn compile_syntax_themes(project: &ProjectPaths, theme: &Theme) -> Result<()> {
     let out_dir = project.export().join("public/theme/syntax");
     std::fs::create_dir_all(&out_dir)
         .with_context(|| format!("failed to create directory {:?}", out_dir))?;
 
-    let themes = ThemeSet::load_from_folder(&themes_dir).expect("failed to read themes directory");
-
-    for (name, theme) in themes.themes.iter() {
-        let css = css_for_theme_with_class_style(theme, crate::code::CLASS_STYLE);
-        let out_path = out_dir.join(name).with_extension("css");
-        std::fs::write(out_path, css)
-            .with_context(|| format!("failed to write theme {:?}", name))?;
+    for name in theme.syntax_themes() {
+        let css = compile_syntax_theme(theme, name)?.ok_or_else(|| {
+            anyhow::anyhow!(
+                "there is no syntax theme called `{}`, the available themes are: {}",
+                name,
+                theme.syntax_theme_names().unwrap_or_default().join(", ")
+            )
+        })?;
+        let out_path = out_dir.join(format!("{}.css", name));
+        std::fs::write(&out_path, css)
+            .with_context(|| format!("failed to write to {:?}", &out_path))?;
     }
 
     Ok(())
 }
 
 /// Compiles the project's theme sass to the exported public directory
-fn compile_sass(project: &ProjectPaths) -> Result<()> {
-    let sass_dir = project.theme().join("sass");
+fn compile_sass(project: &ProjectPaths, theme: &Theme, mode: BuildMode) -> Result<()> {
     let out_dir = project.export().join("public/theme/style");
 
-    let walkdir = WalkDir::new(&sass_dir)
-        .into_iter()
-        .filter_map(|entry| {
-            if let Err(e) = &entry {
-                log::warn!("failed to read entry {:?}", e);
-            }
-            entry.ok()
-        })
-        .filter(|entry| {
-            entry.path().extension() == Some(OsStr::new("scss"))
-                // ignore scss partials
-                && !entry
-                    .path()
-                    .file_name()
-                    .unwrap()
-                    .to_str()
-                    .unwrap()
-                    .starts_with('_')
-        });
+    // sass resolves imports in the file system so the sass files of every
+    // layer of the theme are put together in a single directory
+    theme.with_extracted_dir(Path::new("sass"), |sass_dir| {
+        let walkdir = WalkDir::new(sass_dir)
+            .into_iter()
+            .filter_map(|entry| {
+                if let Err(e) = &entry {
+                    log::warn!("failed to read entry {:?}", e);
+                }
+                entry.ok()
+            })
+            .filter(|entry| {
+                entry.path().extension() == Some(OsStr::new("scss"))
+                    // ignore scss partials
+                    && !entry
+                        .path()
+                        .file_name()
+                        .unwrap()
+                        .to_str()
+                        .unwrap()
+                        .starts_with('_')
+            });
 
-    for entry in walkdir {
-        if entry.metadata().map(|m| m.is_file()).unwrap_or(false) {
-            let path = entry.path();
-            let compiled_sass = sass_rs::compile_file(path, sass_rs::Options::default())
-                // `compile_file` returns an error that doesn't implement `std::error::Error` -.-
-                .map_err(|e| anyhow::anyhow!("{}", e))
-                .with_context(|| format!("failed to compile sass file {:?}", path))?;
-
-            // Put the compiled SASS files under `out_dir` following the same directory
-            // structure they had in `sass_dir` that is, reuse the hierarchy in
-            // brackets in the line below vvv .codasai/sass/[header/style.scss]
-            // -> .codasai/export/preview/public/style/[header/style.css]
-            let relative_path = entry.path().strip_prefix(&sass_dir)?;
-            let mut out_path = out_dir.join(&relative_path);
-            out_path.set_extension("css");
-            let parent_dir = out_path.parent().unwrap();
-
-            anyhow::ensure!(!out_path.exists(), "file already exists {:?}", &out_path);
-
-            std::fs::create_dir_all(&parent_dir)
-                .with_context(|| format!("failed to create directory {:?}", parent_dir))?;
-
-            std::fs::write(&out_path, &compiled_sass)
-                .with_context(|| format!("failed to write to {:?}", out_path))?;
+        for entry in walkdir {
+            if entry.metadata().map(|m| m.is_file()).unwrap_or(false) {
+                let path = entry.path();
+                let compiled_sass = compile_sass_file(path, mode)?;
+
+                // Put the compiled SASS files under `out_dir` following the same directory
+                // structure they had in `sass_dir` that is, reuse the hierarchy in
+                // brackets in the line below vvv .codasai/sass/[header/style.scss]
+                // -> .codasai/export/preview/public/style/[header/style.css]
+                let relative_path = entry.path().strip_prefix(sass_dir)?;
+                let mut out_path = out_dir.join(relative_path);
+                out_path.set_extension("css");
+                let parent_dir = out_path.parent().unwrap();
+
+                anyhow::ensure!(!out_path.exists(), "file already exists {:?}", &out_path);
+
+                std::fs::create_dir_all(parent_dir)
+                    .with_context(|| format!("failed to create directory {:?}", parent_dir))?;
+
+                std::fs::write(&out_path, &compiled_sass)
+                    .with_context(|| format!("failed to write to {:?}", out_path))?;
+            }
         }
-    }
 
-    Ok(())
+        Ok(())
+    })
+}
+
+/// Compiles the sass file at `path` to css, which is compressed in production.
+pub fn compile_sass_file(path: &Path, mode: BuildMode) -> Result<String> {
+    let options = match mode {
+        BuildMode::Development => sass_rs::Options::default(),
+        BuildMode::Production => sass_rs::Options {
+            output_style: sass_rs::OutputStyle::Compressed,
+            ..Default::default()
+        },
+    };
+    sass_rs::compile_file(path, options)
+        // `compile_file` returns an error that doesn't implement `std::error::Error` -.-
+        .map_err(|e| anyhow::anyhow!("{}", e))
+        .with_context(|| format!("failed to compile sass file {:?}", path))
+}
+
+/// Compiles the syntax theme called `name` to css.
+///
+/// Returns `None` if there is no syntax theme called `name`.
+pub fn compile_syntax_theme(theme: &Theme, name: &str) -> Result<Option<String>> {
+    let syntax_theme = theme.syntax_theme(name)?;
+    Ok(syntax_theme.map(|syntax_theme| {
+        css_for_theme_with_class_style(&syntax_theme, crate::code::CLASS_STYLE)
+    }))
 }
 
 /// Exports the static directory provided by the author of the project's theme.
-fn copy_theme_static_dir(project: &ProjectPaths) -> Result<()> {
-    let public = project.theme().join("static");
+fn copy_theme_static_dir(project: &ProjectPaths, theme: &Theme) -> Result<()> {
+    let static_dir = Path::new("static");
     let dest = project.export().join("public/theme");
 
-    copy_dir_contents(&public, &dest)
+    for path in theme.files(static_dir)? {
+        let out_path = dest.join(path.strip_prefix(static_dir)?);
+        let parent = out_path.parent().unwrap();
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create directory {:?}", parent))?;
+
+        std::fs::write(&out_path, theme.file(&path)?.unwrap())
+            .with_context(|| format!("failed to write to {:?}", &out_path))?;
+    }
+
+    Ok(())
 }
 
 /// Exports the static directory provided by the author of the guide.
@@ -113,9 +341,63 @@ fn export_user_static_dir(project: &ProjectPaths) -> Result<()> {
     copy_dir_contents(&public, &dest)
 }
 
+/// Returns the contents of the public file at `path`, relative to the
+/// exported `public/` directory, generated from the sources of the project at
+/// `project` and its theme `theme`.
+///
+/// Returns `None` if the guide doesn't export such a file.
+pub fn read_public_file(project: &Path, theme: &Theme, path: &Path) -> Result<Option<Vec<u8>>> {
+    if let Ok(file) = path.strip_prefix("user") {
+        let user_path = project.join("static").join(file);
+        if !user_path.is_file() {
+            return Ok(None);
+        }
+        return std::fs::read(&user_path)
+            .map(Some)
+            .with_context(|| format!("failed to read file {:?}", &user_path));
+    }
+
+    if let Ok(file) = path.strip_prefix("theme/style") {
+        let source = Path::new("sass").join(file).with_extension("scss");
+        let is_partial = file
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with('_'));
+        if is_partial || theme.file(&source)?.is_none() {
+            return Ok(None);
+        }
+        let css = theme.with_extracted_dir(Path::new("sass"), |sass_dir| {
+            compile_sass_file(
+                &sass_dir.join(file).with_extension("scss"),
+                BuildMode::Development,
+            )
+        })?;
+        return Ok(Some(css.into_bytes()));
+    }
+
+    if let Ok(file) = path.strip_prefix("theme/syntax") {
+        // only the configured themes are exported
+        let name = file.with_extension("");
+        let name = name.to_string_lossy();
+        if file.extension() != Some(OsStr::new("css"))
+            || !theme.syntax_themes().contains(&name.as_ref())
+        {
+            return Ok(None);
+        }
+        let css = compile_syntax_theme(theme, &name)?;
+        return Ok(css.map(String::into_bytes));
+    }
+
+    if let Ok(file) = path.strip_prefix("theme") {
+        let contents = theme.file(&Path::new("static").join(file))?;
+        return Ok(contents.map(|contents| contents.into_owned()));
+    }
+
+    Ok(None)
+}
+
 /// Copies all contents in `dir` to `dest` recursively.
-fn copy_dir_contents(dir: &Path, dest: &Path) -> Result<()> {
-    let walkdir = WalkDir::new(&dir).into_iter().filter_map(|entry| {
+pub fn copy_dir_contents(dir: &Path, dest: &Path) -> Result<()> {
+    let walkdir = WalkDir::new(dir).into_iter().filter_map(|entry| {
         if let Err(e) = &entry {
             log::warn!("failed to read entry {:?}", e);
         }
@@ -124,11 +406,11 @@ fn copy_dir_contents(dir: &Path, dest: &Path) -> Result<()> {
 
     for entry in walkdir {
         if entry.metadata().map(|m| m.is_file()).unwrap_or(false) {
-            let relative_path = entry.path().strip_prefix(&dir)?;
-            let out_path = dest.join(&relative_path);
+            let relative_path = entry.path().strip_prefix(dir)?;
+            let out_path = dest.join(relative_path);
             let parent = out_path.parent().unwrap();
 
-            std::fs::create_dir_all(&parent)
+            std::fs::create_dir_all(parent)
                 .with_context(|| format!("failed to create directory {:?}", parent))?;
 
             std::fs::copy(entry.path(), &out_path).with_context(|| {
@@ -143,3 +425,104 @@ fn copy_dir_contents(dir: &Path, dest: &Path) -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{write_files, TestRepo};
+
+    /// Compiles the syntax themes of a project with the `highlight` config
+    /// `highlight` and the files `files`.
+    fn syntax_themes(highlight: &str, files: &[(&str, &str)]) -> Result<Vec<String>> {
+        let test = TestRepo::project();
+        let config_path = test.path().join(".codasai/guide.toml");
+        let mut config = std::fs::read_to_string(&config_path).unwrap();
+        config.push_str(&format!("\n[highlight]\n{}\n", highlight));
+        std::fs::write(&config_path, config).unwrap();
+        write_files(test.path(), files);
+
+        let export = tempfile::tempdir().unwrap();
+        let mut project = ProjectPaths::from_project(test.path().to_path_buf());
+        project.set_export(export.path().to_path_buf());
+        let theme = Theme::from_project(test.path())?;
+        compile_syntax_themes(&project, &theme)?;
+
+        let mut names = std::fs::read_dir(export.path().join("public/theme/syntax"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        names.sort();
+        Ok(names)
+    }
+
+    #[test]
+    fn bundled_and_project_syntax_themes_are_compiled() {
+        assert_eq!(
+            syntax_themes("light_theme = \"base16-ocean-light\"", &[]).unwrap(),
+            ["ayu-dark.css", "base16-ocean-light.css"]
+        );
+
+        let one_dark = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+            <plist version=\"1.0\"><dict>\n\
+            <key>name</key><string>One Dark</string>\n\
+            <key>settings</key><array><dict><key>settings</key><dict>\n\
+            <key>background</key><string>#282C34</string>\n\
+            </dict></dict></array>\n\
+            </dict></plist>\n";
+        assert_eq!(
+            syntax_themes(
+                "dark_theme = \"one.dark\"",
+                &[(".codasai/theme/syntax/one.dark.tmTheme", one_dark)]
+            )
+            .unwrap(),
+            ["inspired-github.css", "one.dark.css"]
+        );
+    }
+
+    #[test]
+    fn missing_and_invalid_syntax_themes_fail_the_build() {
+        let error = syntax_themes("dark_theme = \"missing\"", &[]).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("there is no syntax theme called `missing`"));
+
+        for name in ["../one", "syntax/one", "one\\\\two", ".."] {
+            let error = syntax_themes(&format!("dark_theme = \"{}\"", name), &[]).unwrap_err();
+            assert!(
+                error
+                    .to_string()
+                    .contains("is not a valid syntax theme name"),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn public_files_are_fingerprinted_by_sha256() {
+        let dir = crate::testing::dir_with_files(&[
+            ("export/public/theme/style/style.css", "abc"),
+            ("export/public/LICENSE", ""),
+        ]);
+        let mut project = ProjectPaths::from_project(dir.path().to_path_buf());
+        project.set_export(dir.path().join("export"));
+        let public_dir = project.export().join("public");
+
+        let manifest = fingerprint_public_files(&project).unwrap();
+        assert_eq!(
+            manifest.get("theme/style/style.css"),
+            Some("theme/style/style.ba7816bf.css")
+        );
+        assert_eq!(manifest.get("LICENSE"), Some("LICENSE.e3b0c442"));
+        assert_eq!(
+            std::fs::read_to_string(public_dir.join("theme/style/style.ba7816bf.css")).unwrap(),
+            "abc"
+        );
+        // the originals are kept for the files that refer to them
+        assert!(public_dir.join("theme/style/style.css").is_file());
+        assert_eq!(
+            manifest.resolve("/public/theme/style/style.css?v=1#top"),
+            "public/theme/style/style.ba7816bf.css?v=1#top"
+        );
+    }
+}
@@ -0,0 +1,382 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use walkdir::WalkDir;
+
+/// What to do with the external resources a built guide loads.
+#[derive(clap::ArgEnum, Clone, Copy)]
+pub enum ExternalResources {
+    /// Logs a warning for every external resource.
+    Warn,
+    /// Fails if there is any external resource.
+    Deny,
+}
+
+/// Link relations that point to other documents instead of loading resources.
+const NAVIGATION_RELATIONS: [&str; 8] = [
+    "alternate",
+    "author",
+    "canonical",
+    "help",
+    "license",
+    "next",
+    "prev",
+    "search",
+];
+
+/// Scans the html and css files in `dir` for resources loaded from other
+/// hosts, e.g. fonts or scripts from a CDN.
+///
+/// Guides that load external resources don't work offline and let the hosts
+/// know who reads them.
+pub fn check(dir: &Path, policy: ExternalResources) -> Result<()> {
+    let mut found = Vec::new();
+
+    for entry in WalkDir::new(dir).sort_by_file_name() {
+        let entry = entry.with_context(|| format!("failed to read directory {:?}", dir))?;
+        let path = entry.path();
+        let urls = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("html") => html_resources(&read(path)?),
+            Some("css") => css_resources(&read(path)?),
+            _ => continue,
+        };
+
+        let relative_path = path.strip_prefix(dir).unwrap();
+        for url in urls {
+            found.push(format!("{} in {:?}", url, relative_path));
+        }
+    }
+
+    if found.is_empty() {
+        return Ok(());
+    }
+
+    match policy {
+        ExternalResources::Warn => {
+            for resource in &found {
+                log::warn!("external resource {}", resource);
+            }
+            Ok(())
+        },
+        ExternalResources::Deny => anyhow::bail!(
+            "the guide loads external resources:\n\n    {}\n\nserve them with the guide, e.g. \
+             from `static/`",
+            found.join("\n    ")
+        ),
+    }
+}
+
+/// Returns the external urls of the resources loaded by the html document
+/// `html`.
+///
+/// Links to other pages are not resources since they are only loaded when
+/// followed.
+fn html_resources(html: &str) -> Vec<String> {
+    let mut urls = Vec::new();
+    let mut rest = html;
+
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        if let Some(comment) = rest.strip_prefix("!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            continue;
+        }
+
+        let name_end = rest
+            .find(|c: char| c.is_whitespace() || c == '>' || c == '/')
+            .unwrap_or(rest.len());
+        let name = rest[..name_end].to_ascii_lowercase();
+        let (attributes, after_tag) = tag_attributes(&rest[name_end..]);
+        rest = after_tag;
+
+        // `href` only loads a resource in tags that aren't links to other
+        // documents
+        let is_navigation_link = matches!(name.as_str(), "a" | "area" | "base")
+            || name == "link"
+                && attributes.iter().any(|(attribute, value)| {
+                    attribute == "rel"
+                        && value.split_whitespace().all(|rel| {
+                            NAVIGATION_RELATIONS.contains(&rel.to_ascii_lowercase().as_str())
+                        })
+                });
+        for (attribute, value) in &attributes {
+            match attribute.as_str() {
+                "src" | "poster" | "data" => urls.push(value.to_string()),
+                "href" | "xlink:href" if !is_navigation_link => urls.push(value.to_string()),
+                // a list of `<url> <descriptor>`
+                "srcset" => urls.extend(
+                    value
+                        .split(',')
+                        .filter_map(|candidate| candidate.split_whitespace().next())
+                        .map(String::from),
+                ),
+                "style" => urls.extend(css_resources(value)),
+                _ => {},
+            }
+        }
+
+        // their contents aren't html
+        if matches!(name.as_str(), "script" | "style" | "textarea" | "title") {
+            let end = rest
+                .to_ascii_lowercase()
+                .find(&format!("</{}", name))
+                .unwrap_or(rest.len());
+            if name == "style" {
+                urls.extend(css_resources(&rest[..end]));
+            }
+            rest = &rest[end..];
+        }
+    }
+
+    urls.retain(|url| is_external(url));
+    urls
+}
+
+/// Parses the attributes of the tag that starts at `tag`, after its name.
+///
+/// Returns the attributes and what follows the tag.
+fn tag_attributes(tag: &str) -> (Vec<(String, String)>, &str) {
+    let mut attributes = Vec::new();
+    let mut rest = tag;
+
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == '/');
+        if rest.is_empty() {
+            return (attributes, rest);
+        }
+        if let Some(after_tag) = rest.strip_prefix('>') {
+            return (attributes, after_tag);
+        }
+
+        let name_end = rest
+            .find(|c: char| c.is_whitespace() || matches!(c, '=' | '>' | '/'))
+            .unwrap_or(rest.len());
+        if name_end == 0 {
+            // a stray `=`
+            rest = &rest[1..];
+            continue;
+        }
+        let name = rest[..name_end].to_ascii_lowercase();
+        rest = rest[name_end..].trim_start();
+
+        let value = match rest.strip_prefix('=') {
+            Some(value) => {
+                let value = value.trim_start();
+                match value.chars().next() {
+                    Some(quote @ ('"' | '\'')) => {
+                        let end = value[1..].find(quote).map_or(value.len(), |end| end + 1);
+                        rest = value.get(end + 1..).unwrap_or_default();
+                        &value[1..end]
+                    },
+                    _ => {
+                        let end = value
+                            .find(|c: char| c.is_whitespace() || c == '>')
+                            .unwrap_or(value.len());
+                        rest = &value[end..];
+                        &value[..end]
+                    },
+                }
+            },
+            None => "",
+        };
+        attributes.push((name, unescape(value.trim())));
+    }
+}
+
+/// Replaces the character references in the attribute value `value`, e.g.
+/// `&#x2f;`, with the characters they stand for.
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::new();
+    let mut rest = value;
+
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let reference = rest.find(';').map(|end| (&rest[1..end], end));
+        let ch = reference.and_then(|(name, _)| match name {
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            _ => {
+                let code = match name.strip_prefix("#x").or_else(|| name.strip_prefix("#X")) {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                    None => name.strip_prefix('#')?.parse().ok()?,
+                };
+                char::from_u32(code)
+            },
+        });
+
+        match (ch, reference) {
+            (Some(ch), Some((_, end))) => {
+                unescaped.push(ch);
+                rest = &rest[end + 1..];
+            },
+            _ => {
+                unescaped.push('&');
+                rest = &rest[1..];
+            },
+        }
+    }
+
+    unescaped.push_str(rest);
+    unescaped
+}
+
+/// Returns the external urls of the resources loaded by the stylesheet `css`,
+/// with `url()` or `@import`.
+fn css_resources(css: &str) -> Vec<String> {
+    let mut css = css.to_string();
+    while let Some(start) = css.find("/*") {
+        let end = css[start..]
+            .find("*/")
+            .map_or(css.len(), |end| start + end + 2);
+        css.replace_range(start..end, "");
+    }
+
+    let mut urls = Vec::new();
+    let trim_quotes = |url: &str| url.trim().trim_matches(['"', '\'']).trim().to_string();
+    for (_, rest) in css.match_indices("url(").map(|(i, _)| css.split_at(i + 4)) {
+        let end = rest.find(')').unwrap_or(rest.len());
+        urls.push(trim_quotes(&rest[..end]));
+    }
+    for (_, rest) in css
+        .match_indices("@import")
+        .map(|(i, _)| css.split_at(i + 7))
+    {
+        let rest = rest.trim_start();
+        if rest.starts_with(['"', '\'']) {
+            let end = rest.find([';', '\n']).unwrap_or(rest.len());
+            let url = rest[..end].split_whitespace().next().unwrap_or_default();
+            urls.push(trim_quotes(url));
+        }
+    }
+
+    urls.retain(|url| is_external(url));
+    urls
+}
+
+/// Returns whether `url` points to another host.
+fn is_external(url: &str) -> bool {
+    let url = url.to_ascii_lowercase();
+    url.starts_with("http://") || url.starts_with("https://") || url.starts_with("//")
+}
+
+fn read(path: &Path) -> Result<String> {
+    let contents = std::fs::read(path).with_context(|| format!("failed to read {:?}", path))?;
+    Ok(String::from_utf8_lossy(&contents).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn html_resources_in_attributes() {
+        let html = r#"<!DOCTYPE html>
+<html>
+<head>
+  <script src="https://cdn.example.com/lib.js"></script>
+  <script SRC=//cdn.example.com/unquoted.js></script>
+  <link rel="stylesheet" href="HTTPS://fonts.example.com/css">
+  <img src="/public/user/local.png" alt="https://not-a-resource.example.com">
+  <video poster='http://media.example.com/poster.jpg' data-src="https://lazy.example.com"></video>
+  <svg><use xlink:href="https://icons.example.com/sprite.svg#icon"></use></svg>
+  <img src="&#x68;ttps://escaped.example.com/a.png">
+</head>
+</html>"#;
+        assert_eq!(
+            html_resources(html),
+            [
+                "https://cdn.example.com/lib.js",
+                "//cdn.example.com/unquoted.js",
+                "HTTPS://fonts.example.com/css",
+                "http://media.example.com/poster.jpg",
+                "https://icons.example.com/sprite.svg#icon",
+                "https://escaped.example.com/a.png",
+            ]
+        );
+    }
+
+    #[test]
+    fn html_srcset() {
+        let html = r#"<img srcset="small.png 1x, https://cdn.example.com/large.png 2x,
+            //cdn.example.com/huge.png 3x">"#;
+        assert_eq!(
+            html_resources(html),
+            [
+                "https://cdn.example.com/large.png",
+                "//cdn.example.com/huge.png"
+            ]
+        );
+    }
+
+    #[test]
+    fn html_links_to_documents() {
+        let html = r#"
+<a href="https://example.com">a link</a>
+<area href="https://example.com/map">
+<base href="https://example.com/">
+<link rel="canonical" href="https://example.com/page">
+<link rel="Next prev" href="https://example.com/next">
+<link rel="alternate stylesheet" href="https://example.com/alt.css">
+<link rel="icon" href="https://example.com/favicon.ico">
+<link rel="preconnect" href="https://fonts.example.com">"#;
+        assert_eq!(
+            html_resources(html),
+            [
+                "https://example.com/alt.css",
+                "https://example.com/favicon.ico",
+                "https://fonts.example.com"
+            ]
+        );
+    }
+
+    #[test]
+    fn html_comments_and_raw_text() {
+        let html = r#"
+<!-- <script src="https://commented.example.com/a.js"></script> -->
+<script>
+  const tag = '<img src="https://script.example.com/a.png">';
+  if (a < b) { load("<link href='https://script.example.com/b.css'>"); }
+</script>
+<style>
+  /* url(https://commented.example.com/font.woff) */
+  @font-face { src: url("https://fonts.example.com/font.woff2"); }
+</style>
+<p>Fonts are loaded from <code>https://fonts.example.com</code>.</p>
+<textarea><img src="https://textarea.example.com/a.png"></textarea>
+<img alt="1 > 0" src="https://images.example.com/after-gt.png">
+<div style="background: url('//images.example.com/bg.png')"></div>"#;
+        assert_eq!(
+            html_resources(html),
+            [
+                "https://fonts.example.com/font.woff2",
+                "https://images.example.com/after-gt.png",
+                "//images.example.com/bg.png"
+            ]
+        );
+    }
+
+    #[test]
+    fn css_urls_and_imports() {
+        let css = r#"
+@import "https://fonts.example.com/a.css";
+@import url(https://fonts.example.com/b.css) screen;
+@import 'local.css';
+/* @import "https://commented.example.com/c.css"; */
+body { background: url( "//images.example.com/bg.png" ) }
+.icon { background: url(data:image/png;base64,AAAA) }
+.logo { background: url(../images/logo.png) }"#;
+        assert_eq!(
+            css_resources(css),
+            [
+                "https://fonts.example.com/b.css",
+                "//images.example.com/bg.png",
+                "https://fonts.example.com/a.css",
+            ]
+        );
+    }
+}
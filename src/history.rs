@@ -0,0 +1,581 @@
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::context::Index;
+
+/// Path of the index file relative to the repository root.
+pub const INDEX_PATH: &str = ".codasai/index.toml";
+
+/// Returns the path of the page with the given `code` relative to the
+/// repository root.
+pub fn page_path(code: &str) -> PathBuf {
+    Path::new("pages").join(format!("{}.md", code))
+}
+
+/// Returns the code of the page at `path`, relative to the repository root, or
+/// `None` if it isn't a page.
+///
+/// Pages are the markdown files directly in `pages/`, the files in its
+/// subdirectories aren't pages.
+pub fn page_code(path: &Path) -> Option<String> {
+    if path.parent() != Some(Path::new("pages")) || path.extension() != Some(OsStr::new("md")) {
+        return None;
+    }
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+}
+
+/// Creates the commit message used for page commits.
+pub fn page_commit_message(title: &str, code: &str) -> String {
+    format!("Add page: {}\nCode: {}", title, code)
+}
+
+/// Returns the first-parent chain of commits reachable from `HEAD`.
+///
+/// The oldest commit comes first.
+pub fn first_parent_chain(repo: &git2::Repository) -> Result<Vec<git2::Oid>> {
+    let head = repo
+        .head()
+        .context("failed to get repository head")?
+        .peel_to_commit()
+        .context("repository head is not a commit")?;
+    first_parent_chain_from(repo, head.id(), None)
+}
+
+/// Returns the first-parent chain of commits reachable from `head`.
+///
+/// If `base` is given, the chain stops at the first commit that is `base` or
+/// one of its ancestors. The oldest commit comes first.
+pub fn first_parent_chain_from(
+    repo: &git2::Repository, head: git2::Oid, base: Option<git2::Oid>,
+) -> Result<Vec<git2::Oid>> {
+    let is_excluded = |oid: git2::Oid| -> Result<bool> {
+        Ok(match base {
+            Some(base) => oid == base || repo.graph_descendant_of(base, oid)?,
+            None => false,
+        })
+    };
+
+    let mut chain = Vec::new();
+    let mut commit = repo.find_commit(head)?;
+    loop {
+        if is_excluded(commit.id())? {
+            break;
+        }
+        chain.push(commit.id());
+        match commit.parent(0) {
+            Ok(parent) => commit = parent,
+            Err(_) => break,
+        }
+    }
+    chain.reverse();
+
+    Ok(chain)
+}
+
+/// Finds the commit in `chain` that adds the page with the given `code`.
+///
+/// Returns its position in `chain`.
+pub fn find_page_commit(repo: &git2::Repository, chain: &[git2::Oid], code: &str) -> Result<usize> {
+    let page_path = page_path(code);
+
+    let mut had_page = false;
+    for (i, oid) in chain.iter().enumerate() {
+        let tree = repo.find_commit(*oid)?.tree()?;
+        let has_page = tree.get_path(&page_path).is_ok();
+        if has_page && !had_page {
+            return Ok(i);
+        }
+        had_page = has_page;
+    }
+
+    anyhow::bail!("there is no saved page with code `{}`", code)
+}
+
+/// Pages added and removed by a commit.
+#[derive(Default)]
+pub struct PageChanges {
+    /// Codes of the added pages.
+    pub added: Vec<String>,
+    /// Codes of the removed pages.
+    pub removed: Vec<String>,
+}
+
+/// Returns the pages added and removed by `commit`.
+///
+/// The commit is compared against its first parent so the pages brought by a
+/// merge commit are the ones its merged branches add.
+pub fn page_changes(repo: &git2::Repository, commit: &git2::Commit) -> Result<PageChanges> {
+    let parent_tree = match commit.parent(0) {
+        Ok(parent) => Some(parent.tree()?),
+        Err(_) => None,
+    };
+    let mut diff_opts = git2::DiffOptions::new();
+    diff_opts.pathspec("pages/");
+    let diff = repo.diff_tree_to_tree(
+        parent_tree.as_ref(),
+        Some(&commit.tree()?),
+        Some(&mut diff_opts),
+    )?;
+
+    let mut changes = PageChanges::default();
+    for delta in diff.deltas() {
+        match delta.status() {
+            git2::Delta::Added => changes
+                .added
+                .extend(delta.new_file().path().and_then(page_code)),
+            git2::Delta::Deleted => changes
+                .removed
+                .extend(delta.old_file().path().and_then(page_code)),
+            _ => {},
+        }
+    }
+
+    Ok(changes)
+}
+
+/// A commit of the first-parent history that adds a page.
+pub struct PageCommit {
+    pub oid: git2::Oid,
+    /// Code of the added page.
+    pub code: String,
+}
+
+/// The pages saved in the first-parent history of a commit.
+pub struct PageHistory {
+    /// Commits that add pages, the oldest first.
+    pub pages: Vec<PageCommit>,
+    /// Problems in the history that don't stop the guide from being built.
+    pub warnings: Vec<String>,
+}
+
+/// Returns the pages saved in the first-parent history of `head`.
+///
+/// Every page must be added by its own commit of the first-parent history.
+/// Merge commits that add pages are rejected since their workspace isn't the
+/// one the page was written with, merges that don't add pages are only
+/// reported since their changes show up in the following page.
+pub fn page_history(repo: &git2::Repository, head: git2::Oid) -> Result<PageHistory> {
+    let mut history = PageHistory {
+        pages: Vec::new(),
+        warnings: Vec::new(),
+    };
+
+    for rev in first_parent_chain_from(repo, head, None)? {
+        let commit = repo.find_commit(rev)?;
+        let is_merge = commit.parent_count() > 1;
+
+        let changes = page_changes(repo, &commit)?;
+        for code in &changes.removed {
+            history.warnings.push(format!(
+                "commit {} removes page `{}`, use `codasai remove` to remove pages from the \
+                 guide's history",
+                rev, code
+            ));
+        }
+
+        match changes.added.as_slice() {
+            [] if is_merge => history.warnings.push(format!(
+                "merge commit {} doesn't add a page, the changes it merges are shown in the \
+                 following page",
+                rev
+            )),
+            [] => {},
+            [code] if is_merge => anyhow::bail!(
+                "page `{}` is added by merge commit {}, pages must be saved in the first-parent \
+                 history, use `codasai integrate` to bring pages from another branch",
+                code,
+                rev
+            ),
+            [code] => history.pages.push(PageCommit {
+                oid: rev,
+                code: code.clone(),
+            }),
+            codes => anyhow::bail!(
+                "commit {} adds multiple pages ({}), every page must be saved in its own commit",
+                rev,
+                codes.join(", ")
+            ),
+        }
+    }
+
+    Ok(history)
+}
+
+/// Checks that `pages` are listed in the same order in `index`.
+///
+/// The index may list more pages, e.g. when it is read from the working
+/// directory and `pages` are those of an older reference.
+pub fn check_index_order(index: &Index, pages: &[PageCommit]) -> Result<()> {
+    for (page_num, page) in pages.iter().enumerate() {
+        let entry = index.entries.get(page_num).ok_or_else(|| {
+            anyhow::anyhow!(
+                "page `{}` added by commit {} is not in the index",
+                &page.code,
+                page.oid
+            )
+        })?;
+        anyhow::ensure!(
+            entry.code == page.code,
+            "page `{}` is the page number {} in the history but the index lists `{}` in that \
+             position",
+            &page.code,
+            page_num + 1,
+            &entry.code
+        );
+    }
+
+    Ok(())
+}
+
+/// Returns a copy of `tree` where the entry at `path` is replaced by `entry`.
+///
+/// `entry` is a tuple containing the object id and its file mode. If `entry`
+/// is `None`, the entry at `path` is removed. Directories left empty are
+/// removed too.
+pub fn tree_with_entry(
+    repo: &git2::Repository, tree: Option<&git2::Tree>, path: &Path,
+    entry: Option<(git2::Oid, i32)>,
+) -> Result<git2::Oid> {
+    let mut components = path.components();
+    let name = components
+        .next()
+        .context("cannot replace the root of a tree")?
+        .as_os_str();
+    let rest = components.as_path();
+
+    let mut builder = repo.treebuilder(tree)?;
+    let entry = if rest.as_os_str().is_empty() {
+        entry
+    } else {
+        let subtree = tree
+            .and_then(|tree| tree.get_name(&name.to_string_lossy()))
+            .and_then(|entry| entry.to_object(repo).ok())
+            .and_then(|object| object.into_tree().ok());
+        let new_subtree = tree_with_entry(repo, subtree.as_ref(), rest, entry)?;
+        if repo.find_tree(new_subtree)?.is_empty() {
+            None
+        } else {
+            Some((new_subtree, i32::from(git2::FileMode::Tree)))
+        }
+    };
+
+    match entry {
+        Some((oid, filemode)) => {
+            builder.insert(name, oid, filemode)?;
+        },
+        None => {
+            if builder.get(name)?.is_some() {
+                builder.remove(name)?;
+            }
+        },
+    }
+
+    Ok(builder.write()?)
+}
+
+/// Returns a copy of `tree` where the entry at `path` is taken from `source`.
+///
+/// If `source` doesn't have an entry at `path`, the entry is removed.
+pub fn tree_with_entry_from(
+    repo: &git2::Repository, tree: Option<&git2::Tree>, path: &Path, source: &git2::Tree,
+) -> Result<git2::Oid> {
+    let entry = source
+        .get_path(path)
+        .ok()
+        .map(|entry| (entry.id(), entry.filemode()));
+    tree_with_entry(repo, tree, path, entry)
+}
+
+/// Returns a copy of `tree` where its index file is modified with `f`.
+///
+/// Trees that don't track the index file are left untouched.
+pub fn tree_with_index(
+    repo: &git2::Repository, tree: &git2::Tree, f: impl FnOnce(&mut Index),
+) -> Result<git2::Oid> {
+    let mut index = match Index::from_tree(repo, tree)? {
+        Some(index) => index,
+        None => return Ok(tree.id()),
+    };
+    f(&mut index);
+
+    let filemode = tree.get_path(Path::new(INDEX_PATH))?.filemode();
+    let new_blob = repo.blob(toml::to_string_pretty(&index)?.as_bytes())?;
+    tree_with_entry(
+        repo,
+        Some(tree),
+        Path::new(INDEX_PATH),
+        Some((new_blob, filemode)),
+    )
+}
+
+/// Recreates `commits` on top of `parent`, rewriting each tree with
+/// `rewrite_tree`.
+///
+/// Authors, committers and messages are kept. Returns the new tip, which is
+/// `parent` if `commits` is empty.
+pub fn replay(
+    repo: &git2::Repository, mut parent: Option<git2::Oid>, commits: &[git2::Oid],
+    mut rewrite_tree: impl FnMut(&git2::Tree) -> Result<git2::Oid>,
+) -> Result<Option<git2::Oid>> {
+    for oid in commits {
+        let commit = repo.find_commit(*oid)?;
+        let tree = repo.find_tree(rewrite_tree(&commit.tree()?)?)?;
+
+        // only the first parent is rewritten, any merged parents are kept
+        let mut parents = Vec::new();
+        if let Some(parent) = parent {
+            parents.push(repo.find_commit(parent)?);
+        }
+        parents.extend(commit.parents().skip(1));
+        let parents = parents.iter().collect::<Vec<_>>();
+
+        let new_commit = repo
+            .commit(
+                None,
+                &commit.author(),
+                &commit.committer(),
+                &String::from_utf8_lossy(commit.message_bytes()),
+                &tree,
+                &parents,
+            )
+            .with_context(|| format!("failed to rewrite commit {}", oid))?;
+        parent = Some(new_commit);
+    }
+
+    Ok(parent)
+}
+
+/// Updates the git index with the working directory's version of `paths`.
+///
+/// The index file is updated too if it's tracked.
+pub fn stage_paths(repo: &git2::Repository, paths: &[&Path]) -> Result<()> {
+    let workdir = repo
+        .workdir()
+        .context("repository has no working directory")?;
+    let mut git_index = repo.index().context("failed to read git index")?;
+
+    let index_path = Path::new(INDEX_PATH);
+    let index_tracked = git_index.get_path(index_path, 0).is_some();
+
+    for path in paths.iter().chain(index_tracked.then_some(&index_path)) {
+        if workdir.join(path).exists() {
+            git_index.add_path(path)?;
+        } else {
+            git_index.remove_path(path)?;
+        }
+    }
+    git_index.write().context("failed to write git index")?;
+
+    Ok(())
+}
+
+/// Points the current branch, or `HEAD` if it is detached, to `new_head`.
+///
+/// The git index is reset to the tree of `new_head`, like `git reset` does, so
+/// that the working directory is compared against the new history. Changes
+/// that were staged are left in the working directory.
+pub fn update_head(repo: &git2::Repository, new_head: git2::Oid, log_message: &str) -> Result<()> {
+    repo.head()
+        .context("failed to get repository head")?
+        .set_target(new_head, log_message)
+        .context("failed to update repository head")?;
+
+    let tree = repo.find_commit(new_head)?.tree()?;
+    let mut git_index = repo.index().context("failed to read git index")?;
+    git_index
+        .read_tree(&tree)
+        .context("failed to reset git index")?;
+    git_index.write().context("failed to write git index")?;
+
+    Ok(())
+}
+
+/// Writes the contents of `dir` in the file system to a tree in `repo`.
+///
+/// Empty directories are skipped since git can't track them.
+pub fn tree_from_dir(repo: &git2::Repository, dir: &Path) -> Result<Option<git2::Oid>> {
+    let mut builder = repo.treebuilder(None)?;
+    let entries =
+        std::fs::read_dir(dir).with_context(|| format!("failed to read directory {:?}", dir))?;
+    for entry in entries {
+        let entry = entry.with_context(|| format!("failed to read directory {:?}", dir))?;
+        let path = entry.path();
+        let (oid, mode) = if path.is_dir() {
+            match tree_from_dir(repo, &path)? {
+                Some(oid) => (oid, git2::FileMode::Tree),
+                None => continue,
+            }
+        } else {
+            let oid = repo
+                .blob_path(&path)
+                .with_context(|| format!("failed to store file {:?}", &path))?;
+            (oid, git2::FileMode::Blob)
+        };
+        builder.insert(
+            entry.file_name().to_string_lossy().as_ref(),
+            oid,
+            mode.into(),
+        )?;
+    }
+
+    if builder.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(builder.write()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{index, TestRepo};
+
+    fn codes(history: &PageHistory) -> Vec<&str> {
+        history
+            .pages
+            .iter()
+            .map(|page| page.code.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn files_in_subdirectories_of_pages_are_not_pages() {
+        let test = TestRepo::new();
+        let head = test.commit(
+            &[],
+            "one",
+            &[
+                ("pages/one.md", "# One"),
+                ("pages/drafts/two.md", "# Two"),
+                ("pages/notes.txt", ""),
+            ],
+        );
+
+        let changes = page_changes(&test.repo, &test.repo.find_commit(head).unwrap()).unwrap();
+        assert_eq!(changes.added, ["one"]);
+        assert_eq!(codes(&page_history(&test.repo, head).unwrap()), ["one"]);
+    }
+
+    #[test]
+    fn merge_commit_adding_a_page_is_an_error() {
+        let test = TestRepo::new();
+        let base = test.commit(&[], "one", &[("pages/one.md", "# One")]);
+        let side = test.commit(&[base], "two", &[("pages/two.md", "# Two")]);
+        let merge = test.commit(&[base, side], "merge", &[("pages/two.md", "# Two")]);
+
+        let error = page_history(&test.repo, merge).err().unwrap();
+        assert!(error.to_string().contains("added by merge commit"));
+    }
+
+    #[test]
+    fn merge_commit_without_pages_is_a_warning() {
+        let test = TestRepo::new();
+        let one = test.commit(&[], "one", &[("pages/one.md", "# One")]);
+        let side = test.commit(&[one], "fix", &[("workspace/main.rs", "fn main() {}")]);
+        let merge = test.commit(
+            &[one, side],
+            "merge",
+            &[("workspace/main.rs", "fn main() {}")],
+        );
+        let two = test.commit(&[merge], "two", &[("pages/two.md", "# Two")]);
+
+        let history = page_history(&test.repo, two).unwrap();
+        assert_eq!(codes(&history), ["one", "two"]);
+        assert_eq!(history.pages[1].oid, two);
+        assert_eq!(history.warnings.len(), 1);
+        assert!(history.warnings[0].contains("doesn't add a page"));
+    }
+
+    #[test]
+    fn commit_adding_multiple_pages_is_an_error() {
+        let test = TestRepo::new();
+        let head = test.commit(
+            &[],
+            "one and two",
+            &[("pages/one.md", "# One"), ("pages/two.md", "# Two")],
+        );
+
+        let error = page_history(&test.repo, head).err().unwrap();
+        assert!(error.to_string().contains("adds multiple pages (one, two)"));
+    }
+
+    #[test]
+    fn removed_pages_are_a_warning() {
+        let test = TestRepo::new();
+        let one = test.commit(&[], "one", &[("pages/one.md", "# One")]);
+        let parent = test.repo.find_commit(one).unwrap();
+        let tree = tree_with_entry(
+            &test.repo,
+            Some(&parent.tree().unwrap()),
+            Path::new("pages/one.md"),
+            None,
+        )
+        .unwrap();
+        let tree = test.repo.find_tree(tree).unwrap();
+        let signature = parent.author();
+        let head = test
+            .repo
+            .commit(None, &signature, &signature, "remove", &tree, &[&parent])
+            .unwrap();
+
+        let history = page_history(&test.repo, head).unwrap();
+        assert_eq!(codes(&history), ["one"]);
+        assert!(history.warnings[0].contains("removes page `one`"));
+    }
+
+    #[test]
+    fn index_order_must_match_history() {
+        let test = TestRepo::new();
+        let one = test.commit(&[], "one", &[("pages/one.md", "# One")]);
+        let two = test.commit(&[one], "two", &[("pages/two.md", "# Two")]);
+        let history = page_history(&test.repo, two).unwrap();
+
+        assert!(check_index_order(&index(&["one", "two"]), &history.pages).is_ok());
+        // pages of newer references may be listed too
+        assert!(check_index_order(&index(&["one", "two", "three"]), &history.pages).is_ok());
+
+        let error = check_index_order(&index(&["two", "one"]), &history.pages)
+            .err()
+            .unwrap();
+        assert!(error
+            .to_string()
+            .contains("page `one` is the page number 1 in the history but the index lists `two`"));
+
+        let error = check_index_order(&index(&["one"]), &history.pages)
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("page `two` added by commit"));
+    }
+
+    #[test]
+    fn rebased_history_keeps_its_pages() {
+        let test = TestRepo::new();
+        let base = test.commit(&[], "base", &[("workspace/main.rs", "fn main() {}")]);
+        let one = test.commit(&[base], "one", &[("pages/one.md", "# One")]);
+        let two = test.commit(&[one], "two", &[("pages/two.md", "# Two")]);
+
+        let new_base = test.commit(&[base], "upstream", &[("workspace/lib.rs", "")]);
+        let new_base_tree = test.repo.find_commit(new_base).unwrap().tree().unwrap();
+        let rebased = replay(&test.repo, Some(new_base), &[one, two], |tree| {
+            tree_with_entry_from(
+                &test.repo,
+                Some(tree),
+                Path::new("workspace/lib.rs"),
+                &new_base_tree,
+            )
+        })
+        .unwrap()
+        .unwrap();
+
+        let history = page_history(&test.repo, rebased).unwrap();
+        assert_eq!(codes(&history), ["one", "two"]);
+        assert!(history.warnings.is_empty());
+        assert!(history
+            .pages
+            .iter()
+            .all(|page| page.oid != one && page.oid != two));
+        assert!(check_index_order(&index(&["one", "two"]), &history.pages).is_ok());
+    }
+}
@@ -1,29 +1,80 @@
+// `minijinja::Error` is large but it's what template functions must return.
+#![allow(clippy::result_large_err)]
+
+mod archive;
+mod bundle;
 mod code;
 mod commands;
+mod compress;
+mod config;
 mod context;
 mod export;
+mod external;
+mod history;
 mod html;
+mod minify;
 mod page;
 mod paths;
+mod target;
+mod template;
+#[cfg(test)]
+mod testing;
+mod theme;
 
 use anyhow::Result;
 use clap::Parser;
-use commands::{build, init, preview, save};
+use commands::{
+    build, deploy, import, init, integrate, new, preview, remove, save, serve, split,
+    theme as theme_command,
+};
 use env_logger::Env;
 
 #[derive(Parser)]
 enum Args {
     /// Initializes a codasai project.
     Init(init::Opts),
+    /// Imports an existing git repository as a guide.
+    ///
+    /// Every selected commit becomes a page whose workspace is the commit's
+    /// tree and whose content is a stub generated from the commit's message.
+    Import(import::Opts),
+    /// Creates a new page from the project's page template.
+    ///
+    /// The page's code is derived from its title.
+    New(new::Opts),
     /// Preview the current unsaved page.
     Preview(preview::Opts),
     /// Build the guide.
     Build(build::Opts),
+    /// Build the guide and serve it locally.
+    Serve(serve::Opts),
+    /// Deploy the built guide to a directory or a git branch.
+    Deploy(deploy::Opts),
     /// Saves the newly added page.
     ///
     /// This makes a git commit that includes the new page, your workspace
-    /// directory and anything that you already have staged.
+    /// directory and the guide's index. Anything else that you have staged is
+    /// left out of the commit.
     Save(save::Opts),
+    /// Integrates the pages written in another branch.
+    ///
+    /// The branch's commits are rebased onto the current branch in order and
+    /// its pages are appended to the index.
+    Integrate(integrate::Opts),
+    /// Removes a saved page.
+    ///
+    /// The workspace changes made in the removed page are folded into the
+    /// following page.
+    Remove(remove::Opts),
+    /// Splits a saved page into two pages.
+    ///
+    /// You will be asked which of the workspace changes belong to the first
+    /// half. The rest of them belong to a new page placed right after it.
+    Split(split::Opts),
+    /// Manages the project's theme.
+    ///
+    /// The project's theme overrides files of the theme built into codasai.
+    Theme(theme_command::Opts),
 }
 
 fn main() -> Result<()> {
@@ -33,8 +84,16 @@ fn main() -> Result<()> {
 
     match args {
         Args::Init(ref opts) => init::execute(opts),
+        Args::Import(ref opts) => import::execute(opts),
+        Args::New(ref opts) => new::execute(opts),
         Args::Preview(ref opts) => preview::execute(opts),
         Args::Build(ref opts) => build::execute(opts),
+        Args::Serve(ref opts) => serve::execute(opts),
+        Args::Deploy(ref opts) => deploy::execute(opts),
         Args::Save(ref opts) => save::execute(opts),
+        Args::Integrate(ref opts) => integrate::execute(opts),
+        Args::Remove(ref opts) => remove::execute(opts),
+        Args::Split(ref opts) => split::execute(opts),
+        Args::Theme(ref opts) => theme_command::execute(opts),
     }
 }
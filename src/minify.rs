@@ -0,0 +1,231 @@
+/// Elements whose contents are copied as they are, either because they aren't
+/// html or because their whitespace is rendered.
+const RAW_ELEMENTS: [&str; 4] = ["pre", "script", "style", "textarea"];
+
+/// Minifies the html document `html`.
+///
+/// Comments are removed and runs of whitespace are collapsed to a single
+/// space, or a line break if they contain one. Whitespace is never removed
+/// entirely since whether it's rendered depends on the document's css. The
+/// contents of `<pre>`, `<textarea>`, `<script>` and `<style>` are kept as they
+/// are.
+pub fn html(html: &str) -> String {
+    let mut minified = String::with_capacity(html.len());
+    // the whitespace that the whitespace since the last output collapses to
+    let mut pending_space = None;
+    let mut rest = html;
+
+    while let Some(ch) = rest.chars().next() {
+        if ch.is_ascii_whitespace() {
+            let end = rest
+                .find(|c: char| !c.is_ascii_whitespace())
+                .unwrap_or(rest.len());
+            if rest[..end].contains('\n') {
+                pending_space = Some('\n');
+            } else if pending_space.is_none() {
+                pending_space = Some(' ');
+            }
+            rest = &rest[end..];
+            continue;
+        }
+
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            continue;
+        }
+
+        minified.extend(pending_space.take());
+        let tag = match tag_name(rest) {
+            Some(tag) => tag,
+            None => {
+                minified.push(ch);
+                rest = &rest[ch.len_utf8()..];
+                continue;
+            },
+        };
+
+        let end = minify_tag(rest, &mut minified);
+        rest = &rest[end..];
+
+        if !tag.is_closing && RAW_ELEMENTS.contains(&tag.name.as_str()) {
+            let contents_end = raw_contents_end(rest, &tag.name);
+            minified.push_str(&rest[..contents_end]);
+            rest = &rest[contents_end..];
+        }
+    }
+    minified.extend(pending_space);
+
+    minified
+}
+
+/// Minifies the html fragment `html`, which is shown in a `<pre>` element,
+/// like the highlighted files of a workspace.
+///
+/// Only comments and the whitespace inside tags are removed since the rest of
+/// the whitespace is rendered.
+pub fn preformatted_html(html: &str) -> String {
+    let mut minified = String::with_capacity(html.len());
+    let mut rest = html;
+
+    while let Some(ch) = rest.chars().next() {
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+        } else if tag_name(rest).is_some() {
+            let end = minify_tag(rest, &mut minified);
+            rest = &rest[end..];
+        } else {
+            minified.push(ch);
+            rest = &rest[ch.len_utf8()..];
+        }
+    }
+
+    minified
+}
+
+struct Tag {
+    /// Lowercase name of the element.
+    name: String,
+    is_closing: bool,
+}
+
+/// Returns the tag that starts at the beginning of `html`, if any.
+fn tag_name(html: &str) -> Option<Tag> {
+    let rest = html.strip_prefix('<')?;
+    let (rest, is_closing) = match rest.strip_prefix('/') {
+        Some(rest) => (rest, true),
+        None => (rest, false),
+    };
+    if !rest.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        return None;
+    }
+
+    let name_end = rest
+        .find(|c: char| c.is_ascii_whitespace() || c == '>' || c == '/')
+        .unwrap_or(rest.len());
+    Some(Tag {
+        name: rest[..name_end].to_ascii_lowercase(),
+        is_closing,
+    })
+}
+
+/// Returns the offset of the end tag of the raw element `name` in `contents`,
+/// which follow its start tag.
+fn raw_contents_end(contents: &str, name: &str) -> usize {
+    // `to_ascii_lowercase` keeps the offsets of the characters
+    let lowercase = contents.to_ascii_lowercase();
+    let end_tag = format!("</{}", name);
+    let mut start = 0;
+
+    while let Some(i) = lowercase[start..].find(&end_tag) {
+        let end = start + i;
+        // `</scripts` doesn't end a `<script>` element
+        match lowercase[end + end_tag.len()..].chars().next() {
+            None | Some('>' | '/') => return end,
+            Some(c) if c.is_ascii_whitespace() => return end,
+            _ => start = end + end_tag.len(),
+        }
+    }
+
+    contents.len()
+}
+
+/// Writes the tag that starts at the beginning of `html` to `minified` with
+/// the whitespace between its attributes collapsed.
+///
+/// Returns the length of the tag in `html`.
+fn minify_tag(html: &str, minified: &mut String) -> usize {
+    let mut quote = None;
+    let mut pending_space = false;
+
+    for (i, ch) in html.char_indices() {
+        match quote {
+            Some(q) => {
+                minified.push(ch);
+                if ch == q {
+                    quote = None;
+                }
+            },
+            None if ch.is_ascii_whitespace() => pending_space = true,
+            None => {
+                if pending_space && !matches!(ch, '>' | '/' | '=') && !minified.ends_with('=') {
+                    minified.push(' ');
+                }
+                pending_space = false;
+                minified.push(ch);
+                match ch {
+                    // quotes inside unquoted values, like `a"b` in
+                    // `data-x=a"b`, don't start a value
+                    '"' | '\'' if minified[..minified.len() - 1].ends_with('=') => quote = Some(ch),
+                    '>' => return i + 1,
+                    _ => {},
+                }
+            },
+        }
+    }
+
+    html.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn whitespace_is_collapsed() {
+        let document = "<!DOCTYPE html>\n\
+                        <html>\n  <body>\n    <p>\n      a   <b>b</b>\t<i>c</i>d\n\
+                        \x20   </p>  <!-- comment -->  </body>\n</html>\n";
+        assert_eq!(
+            html(document),
+            "<!DOCTYPE html>\n<html>\n<body>\n<p>\na <b>b</b> <i>c</i>d\n</p> </body>\n</html>\n"
+        );
+        assert_eq!(
+            html("<span>a</span> <!-- a -->\n<span>b</span>"),
+            "<span>a</span>\n<span>b</span>"
+        );
+    }
+
+    #[test]
+    fn raw_elements_are_kept() {
+        let pre = "<pre><code>fn main() {\n    <span>println!</span>();\n}</code></pre>";
+        assert_eq!(
+            html(&format!("<div>\n  {}\n</div>", pre)),
+            format!("<div>\n{}\n</div>", pre)
+        );
+
+        let textarea = "<TEXTAREA  name=\"a\">\n  a  <!-- b -->\n</TextArea >";
+        assert_eq!(
+            html(textarea),
+            "<TEXTAREA name=\"a\">\n  a  <!-- b -->\n</TextArea>"
+        );
+
+        let script = "<script>\n  const a = \"</div>  </scripts>\";\n</script>";
+        assert_eq!(
+            html(&format!("<p>a</p>  {}  <p>b</p>", script)),
+            format!("<p>a</p> {} <p>b</p>", script)
+        );
+
+        let style = "<style>\n  p::before { content: \"  \"; }\n</style>";
+        assert_eq!(html(style), style);
+    }
+
+    #[test]
+    fn attributes_are_kept() {
+        assert_eq!(
+            html(
+                "<a\n  href = \"/a  b\"   title='say \"hi\" > bye'  \
+                 data-x=a\"b  data-y=\"it's\" >x</a>"
+            ),
+            "<a href=\"/a  b\" title='say \"hi\" > bye' data-x=a\"b data-y=\"it's\">x</a>"
+        );
+        assert_eq!(html("<input  disabled  />"), "<input disabled/>");
+    }
+
+    #[test]
+    fn preformatted_whitespace_is_kept() {
+        assert_eq!(
+            preformatted_html("<span  class=\"a\">  a\n\n</span> <!-- b -->\tc\n"),
+            "<span class=\"a\">  a\n\n</span> \tc\n"
+        );
+    }
+}
@@ -1,12 +1,15 @@
 use std::ffi::OsStr;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use anyhow::{Context, Result};
 use git2::Status;
+use minijinja::value::Value;
 use minijinja::{Environment, Source};
 use pulldown_cmark::Parser;
 
 use crate::context::GuideContext;
+use crate::theme::Theme;
 
 /// Structure used to preprocess markdown files.
 ///
@@ -17,10 +20,14 @@ pub struct PagePreprocessor<'a> {
 }
 
 impl<'a> PagePreprocessor<'a> {
-    pub fn new(ctx: &'a GuideContext) -> Self {
+    /// Creates the preprocessor of the pages of the project at `project`,
+    /// whose theme is `theme`.
+    pub fn new(project: &Path, theme: &Arc<Theme>, ctx: &'a GuideContext) -> Self {
         let mut env = Environment::new();
+        crate::template::add_functions(&mut env, project, theme, ctx);
 
         let url = ctx.base_url.clone();
+        let assets = ctx.assets.clone();
         let static_resource =
             move |_: &minijinja::State, path: String| -> Result<String, minijinja::Error> {
                 let mut url = url.clone();
@@ -28,11 +35,12 @@ impl<'a> PagePreprocessor<'a> {
                 if !url.ends_with('/') {
                     url.push('/');
                 }
-                url.push_str("public/user/");
 
                 let relative_path = path.strip_prefix('/').unwrap_or(&path);
+                let user_path = format!("user/{}", relative_path);
 
-                url.push_str(relative_path);
+                url.push_str("public/");
+                url.push_str(assets.get(&user_path).unwrap_or(&user_path));
 
                 Ok(url)
             };
@@ -45,7 +53,7 @@ impl<'a> PagePreprocessor<'a> {
     pub fn preprocess(&self, name: &str, page: &str) -> Result<String> {
         let mut env = self.env.clone();
         env.add_template(name, page)?;
-        let out = env.get_template(name).unwrap().render(&())?;
+        let out = env.get_template(name).unwrap().render(())?;
 
         Ok(out)
     }
@@ -59,7 +67,7 @@ pub fn markdown_to_html(markdown: &str) -> String {
     ammonia::clean(&page_html_unsafe)
 }
 
-pub fn markdown_parser(markdown: &str) -> Parser {
+pub fn markdown_parser(markdown: &str) -> Parser<'_> {
     let options = pulldown_cmark::Options::all();
     Parser::new_ext(markdown, options)
 }
@@ -74,11 +82,7 @@ pub fn extract_title(page: &str) -> String {
         match event {
             Event::Start(Tag::Heading(_)) => in_heading = true,
             Event::End(Tag::Heading(_)) => in_heading = false,
-            Event::Text(text) => {
-                if in_heading {
-                    return text.to_string();
-                }
-            },
+            Event::Text(text) if in_heading => return text.to_string(),
             _ => {},
         }
     }
@@ -86,28 +90,117 @@ pub fn extract_title(page: &str) -> String {
     String::from("Untitled")
 }
 
-pub fn read_theme_templates(project: &Path) -> Result<Environment> {
-    let templates_dir = project.join(".codasai/theme/templates");
+/// Converts `title` to a string that can be used as an URL path segment.
+///
+/// Only ASCII alphanumeric characters are kept and runs of anything else are
+/// replaced with a single `-`.
+pub fn slugify(title: &str) -> String {
+    let mut slug = String::new();
+    for ch in title.chars() {
+        if ch.is_ascii_alphanumeric() {
+            slug.push(ch.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+
+    let slug = slug.trim_end_matches('-');
+    if slug.is_empty() {
+        String::from("page")
+    } else {
+        slug.to_string()
+    }
+}
+
+/// Joins `fragment` to `base_url`.
+///
+/// `fragment` is always treated as relative to `base_url`.
+pub fn url_join(base_url: &str, fragment: &str) -> String {
+    let fragment = Path::new(fragment);
+    let relative_fragment = fragment.strip_prefix("/").unwrap_or(fragment);
+
+    Path::new(base_url)
+        .join(relative_fragment)
+        .display()
+        .to_string()
+}
+
+/// Reads the templates in `theme`, the theme of the project at `project`, for
+/// pages of `guide`.
+///
+/// Templates are named after their path relative to the theme's `templates/`
+/// directory. The snippets in `.codasai/hooks/` replace the theme's hooks.
+pub fn read_theme_templates(
+    project: &Path, theme: &Arc<Theme>, guide: &GuideContext,
+) -> Result<Environment<'static>> {
+    let templates_dir = Path::new("templates");
 
     let mut engine = Environment::new();
     let mut source = Source::new();
-    source.load_from_path(&templates_dir, &["html"])?;
+    for path in theme.files(templates_dir)? {
+        let name = match crate::theme::template_name(path.strip_prefix(templates_dir)?) {
+            Some(name) => name,
+            None => continue,
+        };
+        let contents = theme.file(&path)?.unwrap();
+        let contents = String::from_utf8(contents.into_owned())
+            .with_context(|| format!("template {:?} is not valid utf-8", &path))?;
+        source
+            .add_template(name, contents)
+            .with_context(|| format!("failed to load template {:?}", &path))?;
+    }
+    add_hooks(project, &mut source)?;
     engine.set_source(source);
+    engine.add_global("theme", Value::from_serializable(&theme.context()));
+    crate::template::add_functions(&mut engine, project, theme, guide);
 
-    fn url_join(
-        _state: &minijinja::State, base_url: String, fragment: String,
-    ) -> Result<String, minijinja::Error> {
-        let fragment = Path::new(&fragment);
-        let relative_fragment = fragment.strip_prefix("/").unwrap_or(fragment);
+    Ok(engine)
+}
 
-        Ok(Path::new(&base_url)
-            .join(relative_fragment)
-            .display()
-            .to_string())
+/// Adds the snippets in the project's `.codasai/hooks/` to `source` as the
+/// templates of the hooks they're named after.
+fn add_hooks(project: &Path, source: &mut Source) -> Result<()> {
+    let hooks_dir = project.join(".codasai/hooks");
+    if !hooks_dir.is_dir() {
+        return Ok(());
     }
-    engine.add_filter("url_join", url_join);
 
-    Ok(engine)
+    let entries = std::fs::read_dir(&hooks_dir)
+        .with_context(|| format!("failed to read directory {:?}", &hooks_dir))?;
+    for entry in entries {
+        let path = entry
+            .with_context(|| format!("failed to read directory {:?}", &hooks_dir))?
+            .path();
+        let hook = path
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        // other files, like backups or notes, are left alone
+        if !path.is_file()
+            || path.extension() != Some(OsStr::new("html"))
+            || !crate::template::HOOKS.contains(&hook.as_str())
+        {
+            log::warn!(
+                "skipping {:?}, which is not a hook, hooks are: {}",
+                &path,
+                crate::template::HOOKS
+                    .iter()
+                    .map(|hook| format!("{}.html", hook))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+            continue;
+        }
+
+        let contents = std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read hook {:?}", &path))?;
+        source
+            .add_template(format!("hooks/{}.html", hook), contents)
+            .with_context(|| format!("failed to load hook {:?}", &path))?;
+    }
+
+    Ok(())
 }
 
 /// Find the unsaved page in the project.
@@ -130,10 +223,7 @@ pub fn find_unsaved_page(project: &Path) -> Result<Option<PathBuf>> {
         })?;
 
         let path = PathBuf::from(path);
-        if status.status() == Status::WT_NEW
-            && path.starts_with("pages")
-            && path.extension() == Some(OsStr::new("md"))
-        {
+        if status.status() == Status::WT_NEW && crate::history::page_code(&path).is_some() {
             anyhow::ensure!(page.is_none(), "there is more that one unsaved page");
             page = Some(path);
         }
@@ -141,3 +231,37 @@ pub fn find_unsaved_page(project: &Path) -> Result<Option<PathBuf>> {
 
     Ok(page)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::{AssetManifest, BuildMode};
+    use crate::testing::{write_files, TestRepo};
+
+    #[test]
+    fn hooks_replace_the_theme_hooks() {
+        let project = TestRepo::project();
+        write_files(
+            project.path(),
+            &[
+                (".codasai/hooks/head.html", "<meta name=\"hook\">"),
+                (".codasai/hooks/head.html~", "{% broken"),
+                (".codasai/hooks/.DS_Store", "\0"),
+                (".codasai/hooks/README.md", "# Hooks"),
+                (".codasai/hooks/drafts/footer.html", "{% broken"),
+            ],
+        );
+
+        let theme = Arc::new(Theme::from_project(project.path()).unwrap());
+        let guide = GuideContext {
+            base_url: "/".to_string(),
+            index: Default::default(),
+            assets: AssetManifest::default(),
+            mode: BuildMode::Development,
+        };
+        let templates = read_theme_templates(project.path(), &theme, &guide).unwrap();
+        let render = |name| templates.get_template(name).unwrap().render(()).unwrap();
+        assert_eq!(render("hooks/head.html"), "<meta name=\"hook\">");
+        assert_eq!(render("hooks/footer.html"), "");
+    }
+}
@@ -3,6 +3,7 @@ use std::path::{Path, PathBuf};
 use anyhow::{Context, Result};
 
 #[allow(unused)]
+#[derive(Clone)]
 pub struct ProjectPaths {
     project: PathBuf,
     codasai: PathBuf,
@@ -10,6 +11,7 @@ pub struct ProjectPaths {
     workspace: PathBuf,
     user_static: PathBuf,
     theme: PathBuf,
+    theme_base: PathBuf,
     export: PathBuf,
     config_file: PathBuf,
     index_file: PathBuf,
@@ -18,20 +20,25 @@ pub struct ProjectPaths {
 #[allow(unused)]
 impl ProjectPaths {
     pub fn new() -> Result<Self> {
-        let project = project()?;
+        Ok(Self::from_project(project()?))
+    }
+
+    /// Returns the paths of the project at `project`.
+    pub fn from_project(project: PathBuf) -> Self {
         let codasai = project.join(".codasai");
 
-        Ok(Self {
+        Self {
             project: project.clone(),
             codasai: codasai.clone(),
             pages: project.join("pages"),
             workspace: project.join("workspace"),
             user_static: project.join("static"),
             theme: codasai.join("theme"),
+            theme_base: codasai.join("theme-base"),
             export: codasai.join("export"),
             config_file: codasai.join("guide.toml"),
             index_file: codasai.join("index.toml"),
-        })
+        }
     }
 
     pub fn set_export(&mut self, path: PathBuf) {
@@ -68,6 +75,14 @@ impl ProjectPaths {
         &self.theme
     }
 
+    /// Get a reference to the project paths's theme base.
+    ///
+    /// It holds the inherited version of the files that the project's theme
+    /// overrides, as of their last update.
+    pub fn theme_base(&self) -> &PathBuf {
+        &self.theme_base
+    }
+
     /// Get a reference to the project paths's export.
     pub fn export(&self) -> &PathBuf {
         &self.export
@@ -84,6 +99,14 @@ impl ProjectPaths {
     }
 }
 
+/// Returns the directory where files used by `purpose`, like extracted
+/// themes, are cached for the project at `project`.
+///
+/// It is under `.codasai/cache/`, which isn't saved with the pages.
+pub fn cache_dir(project: &Path, purpose: &str) -> PathBuf {
+    project.join(".codasai/cache").join(purpose)
+}
+
 pub fn project() -> Result<PathBuf> {
     let mut current_path = Path::new(".")
         .canonicalize()
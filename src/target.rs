@@ -0,0 +1,82 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+/// A static site host the guide can be built for.
+#[derive(clap::ArgEnum, Clone, Copy)]
+pub enum Target {
+    GithubPages,
+    GitlabPages,
+    Netlify,
+}
+
+impl Target {
+    /// Returns the base URL the guide is served under by the host.
+    ///
+    /// Github and Gitlab serve projects under their name unless the repository
+    /// is the user's or group's site, the project is found through the `origin`
+    /// remote of `repo`.
+    pub fn base_url(&self, repo: &git2::Repository) -> Result<String> {
+        let pages_domain = match self {
+            Self::GithubPages => "github.io",
+            Self::GitlabPages => "gitlab.io",
+            Self::Netlify => return Ok(String::from("/")),
+        };
+
+        let remote = repo
+            .find_remote("origin")
+            .context("failed to find the `origin` remote, pass `--base-url` instead")?;
+        let project = remote.url().and_then(project_name).ok_or_else(|| {
+            anyhow::anyhow!(
+                "failed to find the project name in the `origin` remote, pass `--base-url` \
+                 instead"
+            )
+        })?;
+
+        if project.ends_with(&format!(".{}", pages_domain)) {
+            Ok(String::from("/"))
+        } else {
+            Ok(format!("/{}/", project))
+        }
+    }
+
+    /// Exports the files the host needs to serve the guide in `export_dir`.
+    ///
+    /// `start_page` is the page readers are sent to when they visit the root
+    /// of the guide, if it doesn't have its own page.
+    pub fn export_host_files(
+        &self, export_dir: &Path, base_url: &str, start_page: Option<&str>,
+    ) -> Result<()> {
+        match self {
+            // Jekyll ignores files that start with an underscore
+            Self::GithubPages => write(&export_dir.join(".nojekyll"), ""),
+            // `404.html` is all Gitlab needs
+            Self::GitlabPages => Ok(()),
+            Self::Netlify => {
+                let redirects = match start_page {
+                    Some(page) => format!(
+                        "{} {} 302\n",
+                        base_url,
+                        crate::page::url_join(base_url, page)
+                    ),
+                    None => String::new(),
+                };
+                write(&export_dir.join("_redirects"), &redirects)
+            },
+        }
+    }
+}
+
+/// Returns the name of the project in a git remote `url`.
+fn project_name(url: &str) -> Option<&str> {
+    let name = url
+        .trim_end_matches('/')
+        .trim_end_matches(".git")
+        .rsplit(['/', ':'])
+        .next()?;
+    (!name.is_empty()).then_some(name)
+}
+
+fn write(path: &Path, contents: &str) -> Result<()> {
+    std::fs::write(path, contents).with_context(|| format!("failed to write to {:?}", path))
+}
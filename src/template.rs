@@ -0,0 +1,394 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use minijinja::value::Value;
+use minijinja::{Environment, Error, ErrorKind, State};
+
+use crate::context::GuideContext;
+use crate::export::AssetManifest;
+use crate::page::url_join;
+use crate::theme::Theme;
+
+/// Templates the theme includes at fixed places of every page, which projects
+/// fill with the snippets in `.codasai/hooks/`.
+///
+/// A hook called `head` is the template `hooks/head.html`.
+pub const HOOKS: [&str; 4] = ["head", "before-content", "after-content", "footer"];
+
+const MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+const WEEKDAYS: [&str; 7] = [
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+    "Sunday",
+];
+
+/// Adds the functions and filters shared by the theme's templates and the
+/// pages of the project at `project`, whose theme is `theme`, to `env`.
+///
+/// Urls are made relative to the base url of `guide` and point to the
+/// fingerprinted copies of its public files, if any.
+pub fn add_functions(
+    env: &mut Environment, project: &Path, theme: &Arc<Theme>, guide: &GuideContext,
+) {
+    let assets = guide.assets.clone();
+    env.add_filter(
+        "url_join",
+        move |_: &State, base_url: String, fragment: String| {
+            Ok(url_join(&base_url, &assets.resolve(&fragment)))
+        },
+    );
+
+    let project = project.to_path_buf();
+    let theme = Arc::clone(theme);
+    let assets = guide.assets.clone();
+    let assets_base_url = guide.base_url.clone();
+    env.add_function("asset_url", move |_: &State, path: String| {
+        asset_url(&project, &theme, &assets, &assets_base_url, &path)
+    });
+
+    let pages_base_url = guide.base_url.clone();
+    env.add_function("page_url", move |_: &State, code: String| {
+        Ok(url_join(&pages_base_url, &code))
+    });
+
+    env.add_filter("date", |_: &State, date: Value, format: Option<String>| {
+        let timestamp = match date.as_str() {
+            Some(date) => parse_date(date)?,
+            None => i64::try_from(date)?,
+        };
+        format_date(timestamp, format.as_deref().unwrap_or("%Y-%m-%d"))
+    });
+
+    env.add_filter("markdown", |_: &State, markdown: String| {
+        Ok(Value::from_safe_string(crate::page::markdown_to_html(
+            &markdown,
+        )))
+    });
+}
+
+/// Returns the url of the public file at `path`, relative to `public/`, with a
+/// hash of its contents so that browsers don't use stale copies of it.
+///
+/// The url points to the file's fingerprinted copy if there is one, otherwise
+/// the hash is added to its query.
+fn asset_url(
+    project: &Path, theme: &Theme, assets: &AssetManifest, base_url: &str, path: &str,
+) -> Result<String, Error> {
+    if let Some(fingerprinted) = assets.get(path) {
+        return Ok(url_join(base_url, &format!("public/{}", fingerprinted)));
+    }
+
+    let relative_path = path.trim_start_matches('/');
+    let contents = crate::export::read_public_file(project, theme, &PathBuf::from(relative_path))
+        .map_err(|e| {
+            Error::new(
+                ErrorKind::ImpossibleOperation,
+                format!("failed to read public file `{}`: {:#}", path, e),
+            )
+        })?
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::ImpossibleOperation,
+                format!("there is no public file `{}`", path),
+            )
+        })?;
+
+    let url = url_join(base_url, &format!("public/{}", relative_path));
+    Ok(format!(
+        "{}?v={}",
+        url,
+        crate::export::content_hash(&contents)
+    ))
+}
+
+/// Parses a `YYYY-MM-DD` date, optionally followed by a `THH:MM:SS` time in
+/// UTC, to seconds since the Unix epoch.
+///
+/// Years before 1 are numbered astronomically, `0` is 1 BC and `-1` is 2 BC.
+fn parse_date(date: &str) -> Result<i64, Error> {
+    let invalid = || {
+        Error::new(
+            ErrorKind::InvalidArguments,
+            format!("`{}` is not a date like `2021-12-31`", date),
+        )
+    };
+    let number = |digits: Option<&str>| -> Result<i64, Error> {
+        digits
+            .filter(|digits| !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|digits| digits.parse().ok())
+            .ok_or_else(invalid)
+    };
+
+    let (day, time) = match date.split_once(['T', ' ']) {
+        Some((day, time)) => (day, Some(time.trim_end_matches('Z'))),
+        None => (date, None),
+    };
+
+    let (sign, day) = match day.strip_prefix('-') {
+        Some(day) => (-1, day),
+        None => (1, day),
+    };
+    let mut parts = day.splitn(3, '-');
+    let year = parts.next().filter(|year| year.len() <= 6);
+    let year = sign * number(year)?;
+    let month = number(parts.next())?;
+    let day = number(parts.next())?;
+    if !(1..=12).contains(&month) || !(1..=days_in_month(year, month)).contains(&day) {
+        return Err(invalid());
+    }
+
+    let mut seconds = 0;
+    if let Some(time) = time {
+        let mut parts = time.splitn(3, ':');
+        let hour = number(parts.next())?;
+        let minute = number(parts.next())?;
+        let second = match parts.next() {
+            Some(second) => number(Some(second))?,
+            None => 0,
+        };
+        if hour >= 24 || minute >= 60 || second >= 60 {
+            return Err(invalid());
+        }
+        seconds = hour * 3600 + minute * 60 + second;
+    }
+
+    Ok(days_from_civil(year, month, day) * 86400 + seconds)
+}
+
+/// Formats `timestamp`, in seconds since the Unix epoch, in UTC.
+///
+/// `format` supports the `strftime` specifiers `%Y`, `%m`, `%d`, `%e`, `%H`,
+/// `%M`, `%S`, `%B`, `%b`, `%A`, `%a` and `%%`.
+fn format_date(timestamp: i64, format: &str) -> Result<String, Error> {
+    let days = timestamp.div_euclid(86400);
+    let seconds = timestamp.rem_euclid(86400);
+    let (year, month, day) = civil_from_days(days);
+    // the epoch was a thursday
+    let weekday = WEEKDAYS[(days + 3).rem_euclid(7) as usize];
+    let month_name = MONTHS[month as usize - 1];
+
+    let mut formatted = String::new();
+    let mut chars = format.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '%' {
+            formatted.push(ch);
+            continue;
+        }
+
+        match chars.next() {
+            Some('Y') => formatted.push_str(&year.to_string()),
+            Some('m') => formatted.push_str(&format!("{:02}", month)),
+            Some('d') => formatted.push_str(&format!("{:02}", day)),
+            Some('e') => formatted.push_str(&day.to_string()),
+            Some('H') => formatted.push_str(&format!("{:02}", seconds / 3600)),
+            Some('M') => formatted.push_str(&format!("{:02}", seconds / 60 % 60)),
+            Some('S') => formatted.push_str(&format!("{:02}", seconds % 60)),
+            Some('B') => formatted.push_str(month_name),
+            Some('b') => formatted.push_str(&month_name[..3]),
+            Some('A') => formatted.push_str(weekday),
+            Some('a') => formatted.push_str(&weekday[..3]),
+            Some('%') => formatted.push('%'),
+            specifier => {
+                return Err(Error::new(
+                    ErrorKind::InvalidArguments,
+                    format!(
+                        "unsupported date format specifier `%{}`",
+                        specifier.map(String::from).unwrap_or_default()
+                    ),
+                ))
+            },
+        }
+    }
+
+    Ok(formatted)
+}
+
+/// Returns the number of days in `month` of `year`.
+fn days_in_month(year: i64, month: i64) -> i64 {
+    let is_leap_year = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    match month {
+        2 if is_leap_year => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Returns the number of days between the Unix epoch and a date of the
+/// proleptic Gregorian calendar.
+///
+/// See <http://howardhinnant.github.io/date_algorithms.html>.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Returns the year, month and day that are `days` after the Unix epoch.
+///
+/// It is the inverse of [`days_from_civil`].
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::BuildMode;
+    use crate::testing::{write_files, TestRepo};
+
+    #[test]
+    fn functions_and_filters() {
+        let project = TestRepo::project();
+        write_files(project.path(), &[("static/app.js", "app();")]);
+        let theme = Arc::new(Theme::from_project(project.path()).unwrap());
+        let guide = GuideContext {
+            base_url: "/guide/".to_string(),
+            index: Default::default(),
+            assets: AssetManifest::default(),
+            mode: BuildMode::Development,
+        };
+        let mut env = Environment::new();
+        add_functions(&mut env, project.path(), &theme, &guide);
+
+        let render = |source: &str| {
+            let mut env = env.clone();
+            env.add_template("test", source).unwrap();
+            env.get_template("test").unwrap().render(())
+        };
+        assert_eq!(render("{{ page_url('one') }}").unwrap(), "/guide/one");
+        assert_eq!(
+            render("{{ asset_url('/user/app.js') }}").unwrap(),
+            format!(
+                "/guide/public/user/app.js?v={}",
+                crate::export::content_hash(b"app();")
+            )
+        );
+        assert!(render("{{ asset_url('missing.js') }}")
+            .unwrap_err()
+            .to_string()
+            .contains("there is no public file `missing.js`"));
+        assert_eq!(
+            render("{{ '*a* <script>' | markdown }}").unwrap(),
+            "<p><em>a</em> </p>"
+        );
+    }
+
+    #[test]
+    fn dates_round_trip() {
+        for (date, timestamp) in [
+            ("1970-01-01", 0),
+            ("1969-12-31T23:59:59", -1),
+            ("2000-02-29", 951782400),
+            ("2024-02-29T12:30:05", 1709209805),
+            ("2021-12-31T23:59:59", 1640995199),
+            ("1600-02-29", -11670998400),
+            ("-44-03-15", -63549360000),
+            ("-1-12-31T00:00:00", -62167305600),
+        ] {
+            assert_eq!(parse_date(date).unwrap(), timestamp, "{}", date);
+            let format = if date.contains('T') {
+                "%Y-%m-%dT%H:%M:%S"
+            } else {
+                "%Y-%m-%d"
+            };
+            assert_eq!(format_date(timestamp, format).unwrap(), date);
+        }
+    }
+
+    #[test]
+    fn times_are_optional() {
+        assert_eq!(parse_date("2021-12-31 10:20").unwrap(), 1640946000);
+        assert_eq!(parse_date("2021-12-31T10:20:00Z").unwrap(), 1640946000);
+    }
+
+    #[test]
+    fn invalid_dates_are_rejected() {
+        for date in [
+            "2021-02-29",
+            "2100-02-29",
+            "2021-04-31",
+            "2021-02-31",
+            "2021-13-01",
+            "2021-00-10",
+            "2021-01-00",
+            "2021-1-1x",
+            "2021-12-31T24:00",
+            "2021-12-31T25:99:99",
+            "2021-12-31T23:60",
+            "2021-12-31T23:59:60",
+            "2021-12-31T23",
+            "12345678-01-01",
+            "--2021-01-01",
+            "",
+        ] {
+            assert!(parse_date(date).is_err(), "{}", date);
+        }
+    }
+
+    #[test]
+    fn format_specifiers() {
+        // a sunday
+        let timestamp = parse_date("2022-01-02T03:04:05").unwrap();
+        for (format, formatted) in [
+            ("%Y", "2022"),
+            ("%m", "01"),
+            ("%d", "02"),
+            ("%e", "2"),
+            ("%H", "03"),
+            ("%M", "04"),
+            ("%S", "05"),
+            ("%B", "January"),
+            ("%b", "Jan"),
+            ("%A", "Sunday"),
+            ("%a", "Sun"),
+            ("%%", "%"),
+            ("%e %B %Y at %H:%M", "2 January 2022 at 03:04"),
+        ] {
+            assert_eq!(format_date(timestamp, format).unwrap(), formatted);
+        }
+        assert_eq!(format_date(0, "%A").unwrap(), "Thursday");
+        assert_eq!(
+            format_date(-1, "%A %H:%M:%S").unwrap(),
+            "Wednesday 23:59:59"
+        );
+
+        assert!(format_date(0, "%j").is_err());
+        assert!(format_date(0, "%").is_err());
+    }
+}
@@ -0,0 +1,174 @@
+//! Helpers for tests that need files or git repositories.
+
+use std::path::Path;
+
+use tempfile::TempDir;
+
+use crate::context::{Index, IndexEntry};
+
+/// Returns an index with the pages `codes`, titled by their codes.
+pub fn index(codes: &[&str]) -> Index {
+    Index {
+        entries: codes
+            .iter()
+            .map(|code| IndexEntry {
+                name: code.to_string(),
+                code: code.to_string(),
+            })
+            .collect(),
+    }
+}
+
+/// Writes `files`, pairs of paths and contents, to `dir`, along with the
+/// directories they're in.
+pub fn write_files(dir: &Path, files: &[(&str, &str)]) {
+    for (path, contents) in files {
+        let path = dir.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+}
+
+/// Returns a temporary directory with `files` written to it.
+pub fn dir_with_files(files: &[(&str, &str)]) -> TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    write_files(dir.path(), files);
+    dir
+}
+
+/// A git repository in a temporary directory that is removed when dropped.
+pub struct TestRepo {
+    pub dir: TempDir,
+    pub repo: git2::Repository,
+}
+
+impl TestRepo {
+    /// Creates an empty repository.
+    pub fn new() -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = git2::Repository::init(dir.path()).unwrap();
+        Self { dir, repo }
+    }
+
+    /// Creates a codasai project, along with its repository.
+    pub fn project() -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        crate::commands::init::init_project("Test guide", dir.path()).unwrap();
+        let repo = git2::Repository::open(dir.path()).unwrap();
+        Self { dir, repo }
+    }
+
+    /// Creates a project with a page for each of `pages`, pairs of codes and
+    /// contents of the workspace's `main.rs`, with `HEAD` checked out.
+    ///
+    /// The pages are titled by their codes and saved after an initial commit.
+    pub fn guide(pages: &[(&str, &str)]) -> Self {
+        let test = Self::project();
+        let index_file = |codes: &[&str]| toml::to_string_pretty(&index(codes)).unwrap();
+        let mut head = test.commit(
+            &[],
+            "Initial commit",
+            &[(crate::history::INDEX_PATH, &index_file(&[]))],
+        );
+
+        let mut codes = Vec::new();
+        for (code, main) in pages {
+            codes.push(*code);
+            head = test.commit(
+                &[head],
+                &crate::history::page_commit_message(code, code),
+                &[
+                    (&format!("pages/{}.md", code), &format!("# {}\n", code)),
+                    ("workspace/main.rs", main),
+                    (crate::history::INDEX_PATH, &index_file(&codes)),
+                ],
+            );
+        }
+
+        test.set_head(head);
+        test.repo
+            .checkout_head(Some(git2::build::CheckoutBuilder::new().force()))
+            .unwrap();
+        test
+    }
+
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// Creates a commit with `parents` whose tree is the tree of the first
+    /// parent with `files`, pairs of paths and contents, written to it.
+    pub fn commit(
+        &self, parents: &[git2::Oid], message: &str, files: &[(&str, &str)],
+    ) -> git2::Oid {
+        let parents = parents
+            .iter()
+            .map(|oid| self.repo.find_commit(*oid).unwrap())
+            .collect::<Vec<_>>();
+
+        let mut tree = parents.first().map(|parent| parent.tree().unwrap());
+        for (path, contents) in files {
+            let blob = self.repo.blob(contents.as_bytes()).unwrap();
+            let oid = crate::history::tree_with_entry(
+                &self.repo,
+                tree.as_ref(),
+                Path::new(path),
+                Some((blob, i32::from(git2::FileMode::Blob))),
+            )
+            .unwrap();
+            tree = Some(self.repo.find_tree(oid).unwrap());
+        }
+        let tree = match tree {
+            Some(tree) => tree,
+            None => {
+                let oid = self.repo.treebuilder(None).unwrap().write().unwrap();
+                self.repo.find_tree(oid).unwrap()
+            },
+        };
+
+        let signature =
+            git2::Signature::new("Jane Doe", "jane@example.com", &git2::Time::new(0, 0)).unwrap();
+        self.repo
+            .commit(
+                None,
+                &signature,
+                &signature,
+                message,
+                &tree,
+                &parents.iter().collect::<Vec<_>>(),
+            )
+            .unwrap()
+    }
+
+    /// Returns the contents of the file at `path` in the tree of the commit
+    /// `oid`, if any.
+    pub fn file(&self, oid: git2::Oid, path: &str) -> Option<String> {
+        let tree = self.repo.find_commit(oid).unwrap().tree().unwrap();
+        let entry = tree.get_path(Path::new(path)).ok()?;
+        let blob = entry.to_object(&self.repo).unwrap().into_blob().unwrap();
+        Some(String::from_utf8(blob.content().to_vec()).unwrap())
+    }
+
+    /// Returns the paths of the tracked files with staged or unstaged changes,
+    /// along with their status.
+    pub fn changes(&self) -> Vec<(String, git2::Status)> {
+        self.repo
+            .statuses(None)
+            .unwrap()
+            .iter()
+            .filter(|status| {
+                !status
+                    .status()
+                    .intersects(git2::Status::WT_NEW | git2::Status::IGNORED)
+            })
+            .map(|status| (status.path().unwrap().to_string(), status.status()))
+            .collect()
+    }
+
+    /// Points the current branch to `oid`.
+    pub fn set_head(&self, oid: git2::Oid) {
+        let branch = self.repo.find_reference("HEAD").unwrap();
+        let branch = branch.symbolic_target().unwrap().to_string();
+        self.repo.reference(&branch, oid, true, "test").unwrap();
+    }
+}
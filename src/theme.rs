@@ -0,0 +1,464 @@
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+use std::ffi::OsStr;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use syntect::highlighting::ThemeSet;
+use walkdir::WalkDir;
+
+use crate::config::Config;
+
+/// The theme shipped with codasai.
+static BUILTIN_THEME: include_dir::Dir<'_> = include_dir::include_dir!("runtime/theme");
+
+/// Syntax themes bundled with syntect, by the name they are configured with.
+const BUNDLED_SYNTAX_THEMES: &[(&str, &str)] = &[
+    ("base16-eighties-dark", "base16-eighties.dark"),
+    ("base16-mocha-dark", "base16-mocha.dark"),
+    ("base16-ocean-dark", "base16-ocean.dark"),
+    ("base16-ocean-light", "base16-ocean.light"),
+    ("solarized-dark", "Solarized (dark)"),
+    ("solarized-light", "Solarized (light)"),
+];
+
+/// File that describes an installed theme, found at its root.
+const MANIFEST_FILE: &str = "theme.toml";
+
+/// A guide's theme.
+///
+/// The theme is made of layers: the files in the project's theme directory,
+/// the installed theme configured in `guide.toml`, if any, and the built-in
+/// theme. Files in a layer override the files with the same path in the layers
+/// below it so a project only needs the files it changes.
+pub struct Theme {
+    dir: PathBuf,
+    installed: Option<InstalledTheme>,
+    options: toml::value::Table,
+    light_syntax_theme: String,
+    dark_syntax_theme: String,
+}
+
+impl Theme {
+    /// Creates the theme of the project at `project`.
+    ///
+    /// Fails if the installed theme is invalid or the configured options
+    /// aren't provided by it.
+    pub fn from_project(project: &Path) -> Result<Self> {
+        let config = Config::from_project(project)?;
+        let installed = match &config.theme.path {
+            Some(path) => Some(InstalledTheme::open(project, &project.join(path))?),
+            None => None,
+        };
+
+        let mut options = installed
+            .as_ref()
+            .map(|installed| installed.manifest.options.clone())
+            .unwrap_or_default();
+        for (name, value) in config.theme.options {
+            anyhow::ensure!(
+                options.contains_key(&name),
+                "the theme doesn't have the option `{}`",
+                name
+            );
+            options.insert(name, value);
+        }
+
+        Ok(Self {
+            dir: project.join(".codasai/theme"),
+            installed,
+            options,
+            light_syntax_theme: config.highlight.light_theme().to_string(),
+            dark_syntax_theme: config.highlight.dark_theme().to_string(),
+        })
+    }
+
+    /// Returns the contents of the file at `path`, relative to the theme's
+    /// root, or `None` if no layer has it.
+    pub fn file(&self, path: &Path) -> Result<Option<Cow<'static, [u8]>>> {
+        match read_file(&self.dir.join(path))? {
+            Some(contents) => Ok(Some(Cow::Owned(contents))),
+            None => self.inherited_file(path),
+        }
+    }
+
+    /// Returns the contents of the file at `path` that the project's theme
+    /// inherits, that is, the file in the layers below it.
+    pub fn inherited_file(&self, path: &Path) -> Result<Option<Cow<'static, [u8]>>> {
+        if let Some(installed) = &self.installed {
+            if let Some(contents) = read_file(&installed.dir.join(path))? {
+                return Ok(Some(Cow::Owned(contents)));
+            }
+        }
+
+        Ok(BUILTIN_THEME
+            .get_file(path)
+            .map(|file| Cow::Borrowed(file.contents())))
+    }
+
+    /// Returns the paths, relative to the theme's root, of the files under
+    /// `dir` in any layer.
+    pub fn files(&self, dir: &Path) -> Result<Vec<PathBuf>> {
+        let mut files = BTreeSet::new();
+
+        if let Some(builtin_dir) = BUILTIN_THEME.get_dir(dir) {
+            collect_builtin_files(builtin_dir, &mut files);
+        }
+        if let Some(installed) = &self.installed {
+            files.extend(dir_files(&installed.dir, dir)?);
+        }
+        files.extend(dir_files(&self.dir, dir)?);
+
+        Ok(files.into_iter().collect())
+    }
+
+    /// Returns the paths, relative to the theme's root, of the files the
+    /// project overrides.
+    pub fn overrides(&self) -> Result<Vec<PathBuf>> {
+        dir_files(&self.dir, Path::new(""))
+    }
+
+    /// Returns the names of the configured light and dark syntax themes.
+    pub fn syntax_themes(&self) -> [&str; 2] {
+        [&self.light_syntax_theme, &self.dark_syntax_theme]
+    }
+
+    /// Returns the syntax theme called `name`.
+    ///
+    /// Syntax themes are the `.tmTheme` files in the theme's `syntax/`
+    /// directory and the themes bundled with syntect. Returns `None` if there
+    /// is no syntax theme called `name`.
+    ///
+    /// Names are file names, so they can't contain `/`, `\` or `..`.
+    pub fn syntax_theme(&self, name: &str) -> Result<Option<syntect::highlighting::Theme>> {
+        anyhow::ensure!(
+            !name.contains(['/', '\\']) && !name.contains(".."),
+            "`{}` is not a valid syntax theme name, it can't contain `/`, `\\` or `..`",
+            name
+        );
+        let path = Path::new("syntax").join(format!("{}.tmTheme", name));
+        if let Some(contents) = self.file(&path)? {
+            let syntax_theme = ThemeSet::load_from_reader(&mut Cursor::new(contents))
+                .with_context(|| format!("failed to read syntax theme {:?}", &path))?;
+            return Ok(Some(syntax_theme));
+        }
+
+        let bundled = BUNDLED_SYNTAX_THEMES
+            .iter()
+            .find(|(bundled_name, _)| *bundled_name == name);
+        Ok(bundled.map(|(_, key)| ThemeSet::load_defaults().themes.remove(*key).unwrap()))
+    }
+
+    /// Returns the names of the available syntax themes.
+    pub fn syntax_theme_names(&self) -> Result<Vec<String>> {
+        let mut names = BUNDLED_SYNTAX_THEMES
+            .iter()
+            .map(|(name, _)| name.to_string())
+            .collect::<BTreeSet<_>>();
+        for path in self.files(Path::new("syntax"))? {
+            if path.extension() == Some(OsStr::new("tmTheme")) {
+                names.insert(path.file_stem().unwrap().to_string_lossy().into_owned());
+            }
+        }
+        Ok(names.into_iter().collect())
+    }
+
+    /// Returns the data of the theme passed to the templates.
+    pub fn context(&self) -> ThemeContext<'_> {
+        let manifest = self.installed.as_ref().map(|installed| &installed.manifest);
+        ThemeContext {
+            name: manifest.map(|manifest| manifest.name.as_str()),
+            version: manifest.map(|manifest| manifest.version.as_str()),
+            options: &self.options,
+            light_syntax_theme: &self.light_syntax_theme,
+            dark_syntax_theme: &self.dark_syntax_theme,
+        }
+    }
+
+    /// Writes the files under `dir` in every layer to a temporary directory and
+    /// calls `f` with it.
+    ///
+    /// This is needed by tools that resolve files in the file system, like
+    /// Sass's imports. The directory is removed afterwards.
+    pub fn with_extracted_dir<T>(
+        &self, dir: &Path, f: impl FnOnce(&Path) -> Result<T>,
+    ) -> Result<T> {
+        let extracted = tempfile::tempdir().context("failed to create temporary directory")?;
+        self.extract_dir(dir, extracted.path())?;
+        let result = f(extracted.path());
+        if let Err(e) = extracted.close() {
+            log::warn!("failed to remove temporary directory: {}", e);
+        }
+        result
+    }
+
+    /// Writes the files under `dir` in every layer to `dest`.
+    fn extract_dir(&self, dir: &Path, dest: &Path) -> Result<()> {
+        for path in self.files(dir)? {
+            let out_path = dest.join(path.strip_prefix(dir)?);
+            let parent = out_path.parent().unwrap();
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("failed to create directory {:?}", parent))?;
+
+            let contents = self.file(&path)?.unwrap();
+            std::fs::write(&out_path, contents)
+                .with_context(|| format!("failed to write to {:?}", &out_path))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Data of the theme passed to the templates as `theme`.
+#[derive(Serialize)]
+pub struct ThemeContext<'a> {
+    /// Name of the installed theme, if any.
+    pub name: Option<&'a str>,
+    /// Version of the installed theme, if any.
+    pub version: Option<&'a str>,
+    /// Options of the installed theme with the values set in `guide.toml`.
+    pub options: &'a toml::value::Table,
+    /// Name of the syntax theme used for light colors.
+    pub light_syntax_theme: &'a str,
+    /// Name of the syntax theme used for dark colors.
+    pub dark_syntax_theme: &'a str,
+}
+
+/// A third-party theme that can be shared by multiple guides.
+pub struct InstalledTheme {
+    dir: PathBuf,
+    manifest: Manifest,
+}
+
+impl InstalledTheme {
+    /// Opens the theme at `path`, which is either a directory or a tar
+    /// archive.
+    ///
+    /// Archives are extracted to the cache of the project at `project`, where
+    /// they're reused until the archive changes.
+    pub fn open(project: &Path, path: &Path) -> Result<Self> {
+        let dir = if path.is_dir() {
+            path.to_path_buf()
+        } else if path.is_file() && crate::archive::is_archive(path) {
+            extract_archive(project, path)?
+        } else {
+            anyhow::bail!(
+                "theme {:?} must be a directory or a `.tar`, `.tar.gz` or `.tgz` archive",
+                path
+            );
+        };
+
+        let manifest_path = dir.join(MANIFEST_FILE);
+        let manifest = std::fs::read_to_string(&manifest_path)
+            .with_context(|| format!("failed to read theme manifest {:?}", &manifest_path))?;
+        let manifest = toml::from_str(&manifest).with_context(|| {
+            format!("failed to deserialize theme manifest {:?}", &manifest_path)
+        })?;
+
+        let theme = Self { dir, manifest };
+        theme
+            .validate()
+            .with_context(|| format!("invalid theme {:?}", path))?;
+        Ok(theme)
+    }
+
+    /// Returns the theme's manifest.
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    /// Checks that the theme can be used by this version of codasai and that
+    /// its templates are complete.
+    fn validate(&self) -> Result<()> {
+        if let Some(required) = &self.manifest.min_codasai_version {
+            anyhow::ensure!(
+                parse_version(env!("CARGO_PKG_VERSION")) >= parse_version(required),
+                "the theme requires codasai {} or newer",
+                required
+            );
+        }
+
+        let templates_dir = Path::new("templates");
+        anyhow::ensure!(
+            self.dir.join(templates_dir).join("template.html").is_file(),
+            "the theme doesn't provide `templates/template.html`"
+        );
+
+        // the templates included by the theme can come from the built-in theme
+        let theme_templates = dir_files(&self.dir, templates_dir)?;
+        let mut templates = theme_templates.iter().cloned().collect::<BTreeSet<_>>();
+        if let Some(builtin_dir) = BUILTIN_THEME.get_dir(templates_dir) {
+            collect_builtin_files(builtin_dir, &mut templates);
+        }
+        let names = templates
+            .iter()
+            .filter_map(|path| template_name(path.strip_prefix(templates_dir).ok()?))
+            .collect::<BTreeSet<_>>();
+
+        for path in theme_templates {
+            let source = std::fs::read_to_string(self.dir.join(&path))
+                .with_context(|| format!("failed to read template {:?}", &path))?;
+            for included in included_templates(&source) {
+                anyhow::ensure!(
+                    names.contains(included),
+                    "{:?} includes `{}`, which the theme doesn't provide",
+                    &path,
+                    included
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The manifest of an installed theme, read from `theme.toml`.
+#[derive(Deserialize)]
+pub struct Manifest {
+    pub name: String,
+    pub version: String,
+    /// Oldest version of codasai the theme works with.
+    pub min_codasai_version: Option<String>,
+    /// Options guides can set in `guide.toml`, with their default values.
+    #[serde(default)]
+    pub options: toml::value::Table,
+}
+
+/// Returns the name the template at `path`, relative to the theme's
+/// `templates/` directory, is loaded with.
+///
+/// Returns `None` for files that aren't templates.
+pub fn template_name(path: &Path) -> Option<String> {
+    let is_hidden = path
+        .file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+    if is_hidden || path.extension() != Some(OsStr::new("html")) {
+        return None;
+    }
+
+    let name = path
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+    Some(name)
+}
+
+/// Returns the names of the templates that `source` includes, extends or
+/// imports.
+fn included_templates(source: &str) -> Vec<&str> {
+    let mut included = Vec::new();
+    for tag in source.split("{%").skip(1) {
+        let tag = tag.split("%}").next().unwrap_or_default();
+        let tag = tag.trim_start_matches('-').trim_start();
+        let is_include = ["include ", "extends ", "import ", "from "]
+            .iter()
+            .any(|keyword| tag.starts_with(keyword));
+        if !is_include {
+            continue;
+        }
+
+        let name = tag
+            .split_once(['"', '\''])
+            .and_then(|(_, rest)| rest.split_once(['"', '\'']))
+            .map(|(name, _)| name);
+        included.extend(name);
+    }
+    included
+}
+
+/// Extracts the theme archive at `archive` unless it was already extracted.
+///
+/// Returns the root of the theme, which is the archive's only directory if the
+/// archive doesn't have a manifest at its root.
+fn extract_archive(project: &Path, archive: &Path) -> Result<PathBuf> {
+    let cache = crate::paths::cache_dir(project, "theme");
+    let dir = cache.join("theme");
+    let stamp_path = cache.join("stamp");
+
+    let metadata = std::fs::metadata(archive)
+        .with_context(|| format!("failed to read metadata of {:?}", archive))?;
+    let stamp = format!(
+        "{:?} {} {:?}",
+        archive.canonicalize()?,
+        metadata.len(),
+        metadata.modified().ok()
+    );
+
+    if std::fs::read_to_string(&stamp_path).ok().as_ref() != Some(&stamp) {
+        if cache.exists() {
+            std::fs::remove_dir_all(&cache)
+                .with_context(|| format!("failed to remove directory {:?}", &cache))?;
+        }
+        crate::archive::extract(archive, &dir)?;
+        std::fs::write(&stamp_path, &stamp)
+            .with_context(|| format!("failed to write to {:?}", &stamp_path))?;
+    }
+
+    if dir.join(MANIFEST_FILE).is_file() {
+        return Ok(dir);
+    }
+    let entries = std::fs::read_dir(&dir)
+        .with_context(|| format!("failed to read directory {:?}", &dir))?
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("failed to read directory {:?}", &dir))?;
+    match entries.as_slice() {
+        [entry] if entry.path().is_dir() => Ok(entry.path()),
+        _ => Ok(dir),
+    }
+}
+
+/// Parses a version like `0.3.1` into its numbers, ignoring pre-release and
+/// build suffixes.
+fn parse_version(version: &str) -> Vec<u64> {
+    let version = version.split(['-', '+']).next().unwrap_or_default();
+    let mut numbers = version
+        .split('.')
+        .map(|number| number.trim().parse().unwrap_or(0))
+        .collect::<Vec<_>>();
+    // `0.3` and `0.3.0` are the same version
+    while numbers.last() == Some(&0) {
+        numbers.pop();
+    }
+    numbers
+}
+
+/// Returns the contents of the file at `path`, or `None` if it isn't a file.
+fn read_file(path: &Path) -> Result<Option<Vec<u8>>> {
+    if !path.is_file() {
+        return Ok(None);
+    }
+    std::fs::read(path)
+        .map(Some)
+        .with_context(|| format!("failed to read theme file {:?}", path))
+}
+
+/// Returns the paths, relative to `root`, of the files under `dir` in `root`.
+fn dir_files(root: &Path, dir: &Path) -> Result<Vec<PathBuf>> {
+    let full_dir = root.join(dir);
+    if !full_dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut files = Vec::new();
+    for entry in WalkDir::new(&full_dir).sort_by_file_name() {
+        let entry =
+            entry.with_context(|| format!("failed to read theme directory {:?}", &full_dir))?;
+        if entry.file_type().is_file() {
+            files.push(entry.path().strip_prefix(root)?.to_path_buf());
+        }
+    }
+
+    Ok(files)
+}
+
+fn collect_builtin_files(dir: &include_dir::Dir, files: &mut BTreeSet<PathBuf>) {
+    for file in dir.files() {
+        files.insert(file.path().to_path_buf());
+    }
+    for dir in dir.dirs() {
+        collect_builtin_files(dir, files);
+    }
+}